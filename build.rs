@@ -4,301 +4,420 @@ use std::path::PathBuf;
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
+    // grammars compiled at runtime need to be built for the same target we are
+    println!(
+        "cargo:rustc-env=TREE_GREPPER_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+
     // c
-    let c_dir: PathBuf = ["vendor", "tree-sitter-c", "src"].iter().collect();
+    if enabled("c") {
+        let c_dir: PathBuf = ["vendor", "tree-sitter-c", "src"].iter().collect();
 
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-c/src/parser.c");
-    cc::Build::new()
-        .include(&c_dir)
-        .warnings(false)
-        .file(c_dir.join("parser.c"))
-        .compile("tree-sitter-c");
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-c/src/parser.c");
+        cc::Build::new()
+            .include(&c_dir)
+            .warnings(false)
+            .file(c_dir.join("parser.c"))
+            .compile("tree-sitter-c");
+    }
 
     // cpp
-    let cpp_dir: PathBuf = ["vendor", "tree-sitter-cpp", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/parser.c");
-    cc::Build::new()
-        .include(&cpp_dir)
-        .warnings(false)
-        .file(cpp_dir.join("parser.c"))
-        .compile("tree-sitter-cpp");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/scanner.c");
-    cc::Build::new()
-        .include(&cpp_dir)
-        .warnings(false)
-        .file(cpp_dir.join("scanner.c"))
-        .compile("tree_sitter_cpp_scanner");
+    if enabled("cpp") {
+        let cpp_dir: PathBuf = ["vendor", "tree-sitter-cpp", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/parser.c");
+        cc::Build::new()
+            .include(&cpp_dir)
+            .warnings(false)
+            .file(cpp_dir.join("parser.c"))
+            .compile("tree-sitter-cpp");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cpp/src/scanner.c");
+        cc::Build::new()
+            .include(&cpp_dir)
+            .warnings(false)
+            .file(cpp_dir.join("scanner.c"))
+            .compile("tree_sitter_cpp_scanner");
+    }
 
     // cuda
-    let cuda_dir: PathBuf = ["vendor", "tree-sitter-cuda", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cuda/src/parser.c");
-    cc::Build::new()
-        .include(&cuda_dir)
-        .warnings(false)
-        .file(cuda_dir.join("parser.c"))
-        .compile("tree-sitter-cuda");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-cuda/src/scanner.c");
-    cc::Build::new()
-        .include(&cuda_dir)
-        .warnings(false)
-        .file(cuda_dir.join("scanner.c"))
-        .compile("tree_sitter_cuda_scanner");
+    if enabled("cuda") {
+        let cuda_dir: PathBuf = ["vendor", "tree-sitter-cuda", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cuda/src/parser.c");
+        cc::Build::new()
+            .include(&cuda_dir)
+            .warnings(false)
+            .file(cuda_dir.join("parser.c"))
+            .compile("tree-sitter-cuda");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-cuda/src/scanner.c");
+        cc::Build::new()
+            .include(&cuda_dir)
+            .warnings(false)
+            .file(cuda_dir.join("scanner.c"))
+            .compile("tree_sitter_cuda_scanner");
+    }
 
     // elixir
-    let elixir_dir: PathBuf = ["vendor", "tree-sitter-elixir", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/parser.c");
-    cc::Build::new()
-        .include(&elixir_dir)
-        .warnings(false)
-        .file(elixir_dir.join("parser.c"))
-        .compile("tree-sitter-elixir");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/scanner.c");
-    cc::Build::new()
-        .include(&elixir_dir)
-        .warnings(false)
-        .file(elixir_dir.join("scanner.c"))
-        .compile("tree_sitter_elixir_scanner");
+    if enabled("elixir") {
+        let elixir_dir: PathBuf = ["vendor", "tree-sitter-elixir", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/parser.c");
+        cc::Build::new()
+            .include(&elixir_dir)
+            .warnings(false)
+            .file(elixir_dir.join("parser.c"))
+            .compile("tree-sitter-elixir");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elixir/src/scanner.c");
+        cc::Build::new()
+            .include(&elixir_dir)
+            .warnings(false)
+            .file(elixir_dir.join("scanner.c"))
+            .compile("tree_sitter_elixir_scanner");
+    }
 
     // elm
-    let elm_dir: PathBuf = ["vendor", "tree-sitter-elm", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/parser.c");
-    cc::Build::new()
-        .include(&elm_dir)
-        .warnings(false)
-        .file(elm_dir.join("parser.c"))
-        .compile("tree-sitter-elm");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/scanner.c");
-    cc::Build::new()
-        .include(&elm_dir)
-        .warnings(false)
-        .file(elm_dir.join("scanner.c"))
-        .compile("tree_sitter_elm_scanner");
+    if enabled("elm") {
+        let elm_dir: PathBuf = ["vendor", "tree-sitter-elm", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/parser.c");
+        cc::Build::new()
+            .include(&elm_dir)
+            .warnings(false)
+            .file(elm_dir.join("parser.c"))
+            .compile("tree-sitter-elm");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-elm/src/scanner.c");
+        cc::Build::new()
+            .include(&elm_dir)
+            .warnings(false)
+            .file(elm_dir.join("scanner.c"))
+            .compile("tree_sitter_elm_scanner");
+    }
 
     // go
-    let go_dir: PathBuf = ["vendor", "tree-sitter-go", "src"].iter().collect();
+    if enabled("go") {
+        let go_dir: PathBuf = ["vendor", "tree-sitter-go", "src"].iter().collect();
 
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-go/src/parser.c");
-    cc::Build::new()
-        .include(&go_dir)
-        .warnings(false)
-        .file(go_dir.join("parser.c"))
-        .compile("tree-sitter-go");
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-go/src/parser.c");
+        cc::Build::new()
+            .include(&go_dir)
+            .warnings(false)
+            .file(go_dir.join("parser.c"))
+            .compile("tree-sitter-go");
+    }
 
     // haskell
-    let haskell_dir: PathBuf = ["vendor", "tree-sitter-haskell", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/parser.c");
-    cc::Build::new()
-        .include(&haskell_dir)
-        .warnings(false)
-        .file(haskell_dir.join("parser.c"))
-        .compile("tree-sitter-haskell");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/scanner.c");
-    cc::Build::new()
-        .include(&haskell_dir)
-        .warnings(false)
-        .file(haskell_dir.join("scanner.c"))
-        .compile("tree_sitter_haskell_scanner");
+    if enabled("haskell") {
+        let haskell_dir: PathBuf = ["vendor", "tree-sitter-haskell", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/parser.c");
+        cc::Build::new()
+            .include(&haskell_dir)
+            .warnings(false)
+            .file(haskell_dir.join("parser.c"))
+            .compile("tree-sitter-haskell");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-haskell/src/scanner.c");
+        cc::Build::new()
+            .include(&haskell_dir)
+            .warnings(false)
+            .file(haskell_dir.join("scanner.c"))
+            .compile("tree_sitter_haskell_scanner");
+    }
 
     // java
-    let java_dir: PathBuf = ["vendor", "tree-sitter-java", "src"].iter().collect();
+    if enabled("java") {
+        let java_dir: PathBuf = ["vendor", "tree-sitter-java", "src"].iter().collect();
 
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-java/src/parser.c");
-    cc::Build::new()
-        .include(&java_dir)
-        .warnings(false)
-        .file(java_dir.join("parser.c"))
-        .compile("tree-sitter-java");
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-java/src/parser.c");
+        cc::Build::new()
+            .include(&java_dir)
+            .warnings(false)
+            .file(java_dir.join("parser.c"))
+            .compile("tree-sitter-java");
+    }
 
     // javascript
-    let javascript_dir: PathBuf = ["vendor", "tree-sitter-javascript", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/parser.c");
-    cc::Build::new()
-        .include(&javascript_dir)
-        .warnings(false)
-        .file(javascript_dir.join("parser.c"))
-        .compile("tree-sitter-javascript");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/scanner.c");
-    cc::Build::new()
-        .include(&javascript_dir)
-        .warnings(false)
-        .file(javascript_dir.join("scanner.c"))
-        .compile("tree_sitter_javascript_scanner");
+    if enabled("javascript") {
+        let javascript_dir: PathBuf = ["vendor", "tree-sitter-javascript", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/parser.c");
+        cc::Build::new()
+            .include(&javascript_dir)
+            .warnings(false)
+            .file(javascript_dir.join("parser.c"))
+            .compile("tree-sitter-javascript");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-javascript/src/scanner.c");
+        cc::Build::new()
+            .include(&javascript_dir)
+            .warnings(false)
+            .file(javascript_dir.join("scanner.c"))
+            .compile("tree_sitter_javascript_scanner");
+    }
 
     // markdown
-    let markdown_dir: PathBuf = [
-        "vendor",
-        "tree-sitter-markdown",
-        "tree-sitter-markdown",
-        "src",
-    ]
-    .iter()
-    .collect();
+    if enabled("markdown") {
+        let markdown_dir: PathBuf = [
+            "vendor",
+            "tree-sitter-markdown",
+            "tree-sitter-markdown",
+            "src",
+        ]
+        .iter()
+        .collect();
 
-    println!(
-        "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown/src/parser.c"
-    );
-    cc::Build::new()
-        .include(&markdown_dir)
-        .warnings(false)
-        .file(markdown_dir.join("parser.c"))
-        .compile("tree-sitter-markdown");
+        println!(
+            "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown/src/parser.c"
+        );
+        cc::Build::new()
+            .include(&markdown_dir)
+            .warnings(false)
+            .file(markdown_dir.join("parser.c"))
+            .compile("tree-sitter-markdown");
+
+        println!(
+            "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown/src/scanner.c"
+        );
+        cc::Build::new()
+            .include(&markdown_dir)
+            .warnings(false)
+            .file(markdown_dir.join("scanner.c"))
+            .compile("tree_sitter_markdown_scanner");
+    }
+
+    // markdown_inline
+    if enabled("markdown_inline") {
+        let markdown_inline_dir: PathBuf = [
+            "vendor",
+            "tree-sitter-markdown",
+            "tree-sitter-markdown-inline",
+            "src",
+        ]
+        .iter()
+        .collect();
 
-    println!(
-        "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown/src/scanner.c"
-    );
-    cc::Build::new()
-        .include(&markdown_dir)
-        .warnings(false)
-        .file(markdown_dir.join("scanner.c"))
-        .compile("tree_sitter_markdown_scanner");
+        println!(
+            "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown-inline/src/parser.c"
+        );
+        cc::Build::new()
+            .include(&markdown_inline_dir)
+            .warnings(false)
+            .file(markdown_inline_dir.join("parser.c"))
+            .compile("tree-sitter-markdown-inline");
+
+        println!(
+            "cargo:rerun-if-changed=vendor/tree-sitter-markdown/tree-sitter-markdown-inline/src/scanner.c"
+        );
+        cc::Build::new()
+            .include(&markdown_inline_dir)
+            .warnings(false)
+            .file(markdown_inline_dir.join("scanner.c"))
+            .compile("tree_sitter_markdown_inline_scanner");
+    }
 
     // nix
-    let nix_dir: PathBuf = ["vendor", "tree-sitter-nix", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-nix/src/parser.c");
-    cc::Build::new()
-        .include(&nix_dir)
-        .warnings(false)
-        .file(nix_dir.join("parser.c"))
-        .compile("tree-sitter-nix");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-nix/src/scanner.c");
-    cc::Build::new()
-        .include(&nix_dir)
-        .warnings(false)
-        .file(nix_dir.join("scanner.c"))
-        .compile("tree_sitter_nix_scanner");
+    if enabled("nix") {
+        let nix_dir: PathBuf = ["vendor", "tree-sitter-nix", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-nix/src/parser.c");
+        cc::Build::new()
+            .include(&nix_dir)
+            .warnings(false)
+            .file(nix_dir.join("parser.c"))
+            .compile("tree-sitter-nix");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-nix/src/scanner.c");
+        cc::Build::new()
+            .include(&nix_dir)
+            .warnings(false)
+            .file(nix_dir.join("scanner.c"))
+            .compile("tree_sitter_nix_scanner");
+    }
 
     // php
-    let php_dir: PathBuf = ["vendor", "tree-sitter-php", "php", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php/src/parser.c");
-    cc::Build::new()
-        .include(&php_dir)
-        .warnings(false)
-        .file(php_dir.join("parser.c"))
-        .compile("tree-sitter-php");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php/src/scanner.c");
-    cc::Build::new()
-        .include(&php_dir)
-        .warnings(false)
-        .file(php_dir.join("scanner.c"))
-        .compile("tree_sitter_php_scanner");
+    if enabled("php") {
+        let php_dir: PathBuf = ["vendor", "tree-sitter-php", "php", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php/src/parser.c");
+        cc::Build::new()
+            .include(&php_dir)
+            .warnings(false)
+            .file(php_dir.join("parser.c"))
+            .compile("tree-sitter-php");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php/src/scanner.c");
+        cc::Build::new()
+            .include(&php_dir)
+            .warnings(false)
+            .file(php_dir.join("scanner.c"))
+            .compile("tree_sitter_php_scanner");
+    }
+
+    // php_only
+    if enabled("php_only") {
+        let php_only_dir: PathBuf = ["vendor", "tree-sitter-php", "php_only", "src"]
+            .iter()
+            .collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php_only/src/parser.c");
+        cc::Build::new()
+            .include(&php_only_dir)
+            .warnings(false)
+            .file(php_only_dir.join("parser.c"))
+            .compile("tree-sitter-php-only");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-php/php_only/src/scanner.c");
+        cc::Build::new()
+            .include(&php_only_dir)
+            .warnings(false)
+            .file(php_only_dir.join("scanner.c"))
+            .compile("tree_sitter_php_only_scanner");
+    }
 
     // python
-    let python_dir: PathBuf = ["vendor", "tree-sitter-python", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/parser.c");
-    cc::Build::new()
-        .include(&python_dir)
-        .warnings(false)
-        .file(python_dir.join("parser.c"))
-        .compile("tree-sitter-python");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/scanner.c");
-    cc::Build::new()
-        .include(&python_dir)
-        .warnings(false)
-        .file(python_dir.join("scanner.c"))
-        .compile("tree_sitter_python_scanner");
+    if enabled("python") {
+        let python_dir: PathBuf = ["vendor", "tree-sitter-python", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/parser.c");
+        cc::Build::new()
+            .include(&python_dir)
+            .warnings(false)
+            .file(python_dir.join("parser.c"))
+            .compile("tree-sitter-python");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-python/src/scanner.c");
+        cc::Build::new()
+            .include(&python_dir)
+            .warnings(false)
+            .file(python_dir.join("scanner.c"))
+            .compile("tree_sitter_python_scanner");
+    }
 
     // ruby
-    let ruby_dir: PathBuf = ["vendor", "tree-sitter-ruby", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/parser.c");
-    cc::Build::new()
-        .include(&ruby_dir)
-        .warnings(false)
-        .file(ruby_dir.join("parser.c"))
-        .compile("tree-sitter-ruby");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/scanner.c");
-    cc::Build::new()
-        .include(&ruby_dir)
-        .warnings(false)
-        .file(ruby_dir.join("scanner.c"))
-        .compile("tree_sitter_ruby_scanner");
+    if enabled("ruby") {
+        let ruby_dir: PathBuf = ["vendor", "tree-sitter-ruby", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/parser.c");
+        cc::Build::new()
+            .include(&ruby_dir)
+            .warnings(false)
+            .file(ruby_dir.join("parser.c"))
+            .compile("tree-sitter-ruby");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-ruby/src/scanner.c");
+        cc::Build::new()
+            .include(&ruby_dir)
+            .warnings(false)
+            .file(ruby_dir.join("scanner.c"))
+            .compile("tree_sitter_ruby_scanner");
+    }
 
     // rust
-    let rust_dir: PathBuf = ["vendor", "tree-sitter-rust", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/parser.c");
-    cc::Build::new()
-        .include(&rust_dir)
-        .warnings(false)
-        .file(rust_dir.join("parser.c"))
-        .compile("tree-sitter-rust");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/scanner.c");
-    cc::Build::new()
-        .include(&rust_dir)
-        .warnings(false)
-        .file(rust_dir.join("scanner.c"))
-        .compile("tree_sitter_rust_scanner");
+    if enabled("rust") {
+        let rust_dir: PathBuf = ["vendor", "tree-sitter-rust", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/parser.c");
+        cc::Build::new()
+            .include(&rust_dir)
+            .warnings(false)
+            .file(rust_dir.join("parser.c"))
+            .compile("tree-sitter-rust");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-rust/src/scanner.c");
+        cc::Build::new()
+            .include(&rust_dir)
+            .warnings(false)
+            .file(rust_dir.join("scanner.c"))
+            .compile("tree_sitter_rust_scanner");
+    }
 
     // scss
-    let scss_dir: PathBuf = ["vendor", "tree-sitter-scss", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-scss/src/parser.c");
-    cc::Build::new()
-        .include(&scss_dir)
-        .warnings(false)
-        .file(scss_dir.join("parser.c"))
-        .compile("tree-sitter-scss");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-scss/src/scanner.c");
-    cc::Build::new()
-        .include(&scss_dir)
-        .warnings(false)
-        .file(scss_dir.join("scanner.c"))
-        .compile("tree_sitter_scss_scanner");
+    if enabled("sass") {
+        let scss_dir: PathBuf = ["vendor", "tree-sitter-scss", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-scss/src/parser.c");
+        cc::Build::new()
+            .include(&scss_dir)
+            .warnings(false)
+            .file(scss_dir.join("parser.c"))
+            .compile("tree-sitter-scss");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-scss/src/scanner.c");
+        cc::Build::new()
+            .include(&scss_dir)
+            .warnings(false)
+            .file(scss_dir.join("scanner.c"))
+            .compile("tree_sitter_scss_scanner");
+    }
+
+    // tsx
+    if enabled("tsx") {
+        let tsx_dir: PathBuf = ["vendor", "tree-sitter-typescript", "tsx", "src"]
+            .iter()
+            .collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/tsx/src/parser.c");
+        cc::Build::new()
+            .include(&tsx_dir)
+            .warnings(false)
+            .file(tsx_dir.join("parser.c"))
+            .compile("tree-sitter-tsx");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/tsx/src/scanner.c");
+        cc::Build::new()
+            .include(&tsx_dir)
+            .warnings(false)
+            .file(tsx_dir.join("scanner.c"))
+            .compile("tree_sitter_tsx_scanner");
+    }
 
     // typescript
-    let typescript_dir: PathBuf = ["vendor", "tree-sitter-typescript", "typescript", "src"]
-        .iter()
-        .collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/parser.c");
-    cc::Build::new()
-        .include(&typescript_dir)
-        .warnings(false)
-        .file(typescript_dir.join("parser.c"))
-        .compile("tree-sitter-typescript");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/scanner.c");
-    cc::Build::new()
-        .include(&typescript_dir)
-        .warnings(false)
-        .file(typescript_dir.join("scanner.c"))
-        .compile("tree_sitter_typescript_scanner");
+    if enabled("typescript") {
+        let typescript_dir: PathBuf = ["vendor", "tree-sitter-typescript", "typescript", "src"]
+            .iter()
+            .collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/parser.c");
+        cc::Build::new()
+            .include(&typescript_dir)
+            .warnings(false)
+            .file(typescript_dir.join("parser.c"))
+            .compile("tree-sitter-typescript");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-typescript/typescript/src/scanner.c");
+        cc::Build::new()
+            .include(&typescript_dir)
+            .warnings(false)
+            .file(typescript_dir.join("scanner.c"))
+            .compile("tree_sitter_typescript_scanner");
+    }
 
     // powershell
-    let powershell_dir: PathBuf = ["vendor", "tree-sitter-powershell", "src"].iter().collect();
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-powershell/src/parser.c");
-    cc::Build::new()
-        .include(&powershell_dir)
-        .warnings(false)
-        .file(powershell_dir.join("parser.c"))
-        .compile("tree-sitter-powershell");
-
-    println!("cargo:rerun-if-changed=vendor/tree-sitter-powershell/src/scanner.c");
-    cc::Build::new()
-        .include(&powershell_dir)
-        .warnings(false)
-        .file(powershell_dir.join("scanner.c"))
-        .compile("tree_sitter_powershell_scanner");
+    if enabled("powershell") {
+        let powershell_dir: PathBuf = ["vendor", "tree-sitter-powershell", "src"].iter().collect();
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-powershell/src/parser.c");
+        cc::Build::new()
+            .include(&powershell_dir)
+            .warnings(false)
+            .file(powershell_dir.join("parser.c"))
+            .compile("tree-sitter-powershell");
+
+        println!("cargo:rerun-if-changed=vendor/tree-sitter-powershell/src/scanner.c");
+        cc::Build::new()
+            .include(&powershell_dir)
+            .warnings(false)
+            .file(powershell_dir.join("scanner.c"))
+            .compile("tree_sitter_powershell_scanner");
+    }
+}
+
+/// Whether the cargo feature for a grammar is turned on.
+fn enabled(feature: &str) -> bool {
+    std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
 }
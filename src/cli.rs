@@ -1,38 +1,55 @@
-use crate::extractor::Extractor;
-use crate::extractor_chooser::ExtractorChooser;
-use crate::language::Language;
 use anyhow::{bail, Context, Error, Result};
 use clap::{crate_authors, crate_version, Arg, ArgAction, ArgMatches, Command};
 use itertools::Itertools;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use tree_grepper::count::CountBy;
+use tree_grepper::encoding::Encoding;
+use tree_grepper::file_types::FileTypes;
+use tree_grepper::grammar::{self, Grammar};
+use tree_grepper::input::Input;
+use tree_grepper::limits;
+use tree_grepper::listing::ListFiles;
+use tree_grepper::printer::HyperlinkFormat;
+use tree_grepper::rewriter::Rewrite;
+use tree_grepper::skip;
+use tree_grepper::{Language, Searcher, SearcherBuilder};
 
 pub enum Invocation {
     DoQuery(QueryOpts),
+    DoRewrite(RewriteOpts),
     ShowLanguages,
     ShowTree(TreeOpts),
+    ShowTypes(FileTypes),
 }
 
 #[derive(Debug)]
 pub struct QueryOpts {
-    pub extractors: Vec<Extractor>,
-    pub paths: Vec<PathBuf>,
-    pub git_ignore: bool,
+    pub searcher: Searcher,
     pub format: QueryFormat,
-    pub sort: bool,
+    pub count: Option<CountBy>,
+    pub list_files: Option<ListFiles>,
+    pub color: ColorChoice,
+    pub heading: bool,
+    pub hyperlinks: Option<HyperlinkFormat>,
+    pub stats: bool,
+    pub json_errors: bool,
 }
 
-impl QueryOpts {
-    pub fn extractor_chooser(&self) -> Result<ExtractorChooser> {
-        ExtractorChooser::from_extractors(&self.extractors)
-    }
+#[derive(Debug)]
+pub struct RewriteOpts {
+    pub searcher: Searcher,
+    pub rewrite: Rewrite,
+    pub write: bool,
 }
 
 #[derive(Debug)]
 pub struct TreeOpts {
     pub language: Language,
-    pub path: PathBuf,
+    pub input: Input,
+    pub encoding: Encoding,
 }
 
 impl Invocation {
@@ -63,18 +80,234 @@ impl Invocation {
                     .value_names(["LANGUAGE", "QUERY"])
                     .required_unless_present("languages")
                     .required_unless_present("show-tree")
+                    .required_unless_present("query-file")
+                    .required_unless_present("queries-file")
+                    .required_unless_present("type-list")
                     .conflicts_with("languages")
                     .conflicts_with("show-tree")
                     .num_args(1..)
                     .action(ArgAction::Append)
             )
+            .arg(
+                Arg::new("query-file")
+                    .long("query-file")
+                    .help("a language and a file with a query to perform")
+                    .long_help("a language and a file with a query to perform. This works just like -q, but saves you from quoting long queries on the command line.")
+                    .number_of_values(2)
+                    .value_names(["LANGUAGE", "PATH"])
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("queries-file")
+                    .long("queries-file")
+                    .value_name("PATH")
+                    .help("a file with queries for several languages")
+                    .long_help("a file with queries for several languages. Start the queries for each language with a `; language: NAME` line, like `; language: javascript`.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .action(ArgAction::Append)
+            )
             .arg(
                 Arg::new("no-gitignore")
                     .long("no-gitignore")
+                    .action(ArgAction::SetTrue)
                     .help("don't use git's ignore and exclude files to filter files")
                     .conflicts_with("languages")
                     .conflicts_with("show-tree")
             )
+            .arg(
+                Arg::new("injections")
+                    .long("injections")
+                    .action(ArgAction::SetTrue)
+                    .help("also search code embedded in other files, like code blocks in Markdown")
+                    .long_help("also search code embedded in other files: fenced code blocks in Markdown (in the language named after the opening fence), doctests in Python strings, and code blocks in Rust doc comments. Markdown files are searched for this even if you don't have a Markdown query. Positions point into the file the code is embedded in.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("detect-language")
+                    .long("detect-language")
+                    .action(ArgAction::SetTrue)
+                    .help("also pick languages for files by their shebang, editor modeline, or .gitattributes")
+                    .long_help("also pick languages for files by what's in them. A `linguist-language` attribute in .gitattributes wins over globs. Files that no glob matches get their first few bytes checked for a shebang (like `#!/usr/bin/env python3`), an Emacs modeline (like `-*- mode: ruby -*-`), or a Vim modeline (like `vim: set ft=javascript:`).")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("include-generated")
+                    .long("include-generated")
+                    .action(ArgAction::SetTrue)
+                    .help("search generated and vendored code too")
+                    .long_help("search generated and vendored code too. Without this, we skip files marked `linguist-generated` or `linguist-vendored` in .gitattributes, and files with `@generated` or `DO NOT EDIT` in their first few lines. Files you name on the command line are always searched.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("include-minified")
+                    .long("include-minified")
+                    .action(ArgAction::SetTrue)
+                    .help("search minified code too")
+                    .long_help("search minified code too. Without this, we skip files with a line over 2000 bytes long near the start, since that's how bundlers and minifiers write them. Files you name on the command line are always searched.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("max-filesize")
+                    .long("max-filesize")
+                    .value_name("SIZE")
+                    .help("skip files bigger than SIZE (like `500K` or `2M`)")
+                    .long_help("skip files bigger than SIZE, in bytes, or with a K, M, or G suffix (like `500K` or `2M`.) This applies to files you name on the command line too.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("changed-within")
+                    .long("changed-within")
+                    .value_name("DURATION")
+                    .help("only search files modified in the last DURATION (like `7d` or `12h`)")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("newer-than")
+                    .long("newer-than")
+                    .value_name("FILE")
+                    .help("only search files modified more recently than FILE")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .short('v')
+                    .action(ArgAction::SetTrue)
+                    .help("say why each skipped file was skipped (on stderr)")
+                    .long_help("say why each file we could have searched was skipped (because it's generated, binary, minified, too big, or not modified recently) on stderr. With --json-errors, these go in the output as `{\"type\": \"skipped\", \"path\": ..., \"reason\": ...}` records instead.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("encoding")
+                    .long("encoding")
+                    .short('E')
+                    .value_name("ENCODING")
+                    .default_value("auto")
+                    .help("how to read the text in files")
+                    .long_help("how to read the text in files: `auto`, `utf-8`, `utf-16le`, `utf-16be`, `latin-1`, or `windows-1252`. `auto` is UTF-8, and bytes that aren't valid UTF-8 show up as `�` in output. Whatever this is, files that start with a byte order mark are read as the encoding it's for. Positions always count bytes in the file, so columns in UTF-16 files are twice what you might expect.")
+                    .conflicts_with("languages")
+            )
+            .arg(
+                Arg::new("fail-fast")
+                    .long("fail-fast")
+                    .action(ArgAction::SetTrue)
+                    .help("stop at the first file that can't be searched")
+                    .long_help("stop at the first file that can't be searched (or directory that can't be walked.) Without this, each problem is printed to stderr and the search keeps going, but tree-grepper exits with status 2 at the end. Rewrites always stop at the first problem.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("parse-timeout")
+                    .long("parse-timeout")
+                    .value_name("DURATION")
+                    .help("skip files that take longer than DURATION (like `500ms` or `2s`) to parse")
+                    .long_help("skip files that take longer than DURATION (like `500ms` or `2s`) to parse. Skipped files are printed to stderr and counted in --stats, but they don't make tree-grepper exit with an error. Injections and notebook cells each get their own DURATION.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("query-timeout")
+                    .long("query-timeout")
+                    .value_name("DURATION")
+                    .help("skip files that take longer than DURATION to run the queries over")
+                    .long_help("skip files that take longer than DURATION (like `500ms` or `2s`) to run the queries over, once they're parsed. These are reported like --parse-timeout.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("deadline")
+                    .long("deadline")
+                    .value_name("DURATION")
+                    .help("stop searching after DURATION (like `30s` or `5m`)")
+                    .long_help("stop searching after DURATION (like `30s` or `5m`), and print the results found so far. Files that are being searched at the time are skipped, and files we haven't gotten to aren't searched at all. A note goes to stderr when this happens.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("json-errors")
+                    .long("json-errors")
+                    .action(ArgAction::SetTrue)
+                    .help("put errors in JSON output, alongside the results")
+                    .long_help("put errors in JSON output, alongside the results, instead of printing them to stderr. Each result gets `\"type\": \"match\"`, and each error looks like `{\"type\": \"error\", \"path\": ..., \"kind\": ..., \"message\": ...}`, where `kind` is `read`, `parse`, `encoding`, or `walk`. Files skipped for taking too long look like `{\"type\": \"skipped\", \"path\": ..., \"reason\": ...}`. Only works with the JSON formats.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with("rewrite")
+            )
+            .arg(
+                Arg::new("stats")
+                    .long("stats")
+                    .action(ArgAction::SetTrue)
+                    .help("print how many files were searched and skipped (to stderr) after the results")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("stdin")
+                    .long("stdin")
+                    .value_name("LANGUAGE")
+                    .help("search stdin as LANGUAGE instead of files")
+                    .conflicts_with("PATHS")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("stdin-label")
+                    .long("stdin-label")
+                    .value_name("NAME")
+                    .help("the file name to show for stdin in results")
+                    .requires("stdin")
+            )
+            .arg(
+                Arg::new("type-add")
+                    .long("type-add")
+                    .help("parse files matching GLOB as LANGUAGE, too")
+                    .long_help("parse files matching GLOB as LANGUAGE, too. For example, `--type-add javascript Jakefile`.")
+                    .number_of_values(2)
+                    .value_names(["LANGUAGE", "GLOB"])
+                    .action(ArgAction::Append)
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("parse-as")
+                    .long("parse-as")
+                    .help("parse files matching GLOB as LANGUAGE instead of the language they would usually be")
+                    .long_help("parse files matching GLOB as LANGUAGE instead of the language they would usually be. For example, `--parse-as '*.jsx' typescript`. GLOB has to be exactly the same as in --type-list.")
+                    .number_of_values(2)
+                    .value_names(["GLOB", "LANGUAGE"])
+                    .action(ArgAction::Append)
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("header-language")
+                    .long("header-language")
+                    .value_name("LANGUAGE")
+                    .help("search .h files only as LANGUAGE")
+                    .long_help("search .h files only as LANGUAGE. Without this, a .h file is searched with the queries for every language that claims it (C, C++, and CUDA.)")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("type-list")
+                    .long("type-list")
+                    .action(ArgAction::SetTrue)
+                    .help("print the globs for each language (including changes from --type-add and --parse-as)")
+                    .conflicts_with_all(["additional-query", "query-file", "queries-file"])
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
             .arg(
                 Arg::new("PATHS")
                     .default_value(".")
@@ -91,14 +324,171 @@ impl Invocation {
                     .conflicts_with("languages")
                     .conflicts_with("show-tree")
             )
+            .arg(
+                Arg::new("color")
+                    .long("color")
+                    .value_name("WHEN")
+                    .value_parser(["auto", "always", "never"])
+                    .default_value("auto")
+                    .help("when to use colors in lines output")
+                    .long_help("when to use colors in lines output. `auto` uses colors when printing to a terminal, unless the NO_COLOR environment variable is set.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("heading")
+                    .long("heading")
+                    .action(ArgAction::SetTrue)
+                    .help("print each file name once, above its matches, instead of on every line")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with("rewrite")
+            )
+            .arg(
+                Arg::new("hyperlink-format")
+                    .long("hyperlink-format")
+                    .value_name("FORMAT")
+                    .help("link match positions to files in terminals that support it")
+                    .long_help("link match positions to files in terminals that support it (only when colors are on.) Use `default` for file:// links, `vscode` to open files in VS Code, or your own template with {path}, {line}, and {column} in it.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with("rewrite")
+            )
             .arg(
                 Arg::new("sort")
                     .long("sort")
+                    .action(ArgAction::SetTrue)
                     .help("sort matches stably")
-                    .long_help("sort matches stably. If this is not specified, output ordering will vary because due to parallelism. Caution: this adds a worst-case `O(n * log(n))` overhead, where `n` is the number of files matched. Avoid it if possible if you care about performance.")
+                    .long_help("sort matches stably. If this is not specified, output ordering will vary because due to parallelism. Results are still printed as soon as they're ready, but a slow file will hold up the results that sort after it, and the filesystem is walked with a single thread.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("threads")
+                    .long("threads")
+                    .short('j')
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("how many threads to use for parsing files (defaults to the number of CPUs)")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("after-context")
+                    .long("after-context")
+                    .short('A')
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("show NUM lines after each match")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("before-context")
+                    .long("before-context")
+                    .short('B')
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("show NUM lines before each match")
                     .conflicts_with("languages")
                     .conflicts_with("show-tree")
             )
+            .arg(
+                Arg::new("context")
+                    .long("context")
+                    .short('C')
+                    .value_name("NUM")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("show NUM lines before and after each match")
+                    .long_help("show NUM lines before and after each match. -A and -B take precedence over this. In JSON output, the lines (including the ones the match is on) are in a `context` field on each match.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+            )
+            .arg(
+                Arg::new("count")
+                    .long("count")
+                    .short('c')
+                    .action(ArgAction::SetTrue)
+                    .help("print how many matches each file has instead of the matches")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with_all(["after-context", "before-context", "context"])
+            )
+            .arg(
+                Arg::new("count-by")
+                    .long("count-by")
+                    .value_name("GROUP")
+                    .value_parser(["capture"])
+                    .help("like --count, but break the counts down further")
+                    .long_help("like --count, but break the counts down further. `capture` gives the number of matches for each capture name in each file.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with_all(["after-context", "before-context", "context"])
+            )
+            .arg(
+                Arg::new("files-with-matches")
+                    .long("files-with-matches")
+                    .short('l')
+                    .action(ArgAction::SetTrue)
+                    .help("only print the paths of files with at least one match")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with_all(["after-context", "before-context", "context"])
+                    .conflicts_with_all(["count", "count-by"])
+            )
+            .arg(
+                Arg::new("files-without-match")
+                    .long("files-without-match")
+                    .short('L')
+                    .action(ArgAction::SetTrue)
+                    .help("only print the paths of files without any matches")
+                    .long_help("only print the paths of files without any matches. Only files in the languages you're querying are considered.")
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with("files-with-matches")
+                    .conflicts_with_all(["after-context", "before-context", "context"])
+                    .conflicts_with_all(["count", "count-by"])
+            )
+            .arg(
+                Arg::new("rewrite")
+                    .long("rewrite")
+                    .help("replace the text of a capture using a template")
+                    .long_help("replace the text of a capture using a template. The template can refer to other captures in the same match by name, like `{fn}({args}, ctx)`. Use `{{` and `}}` for literal braces. Prints a unified diff unless --write is given.")
+                    .number_of_values(2)
+                    .value_names(["CAPTURE", "TEMPLATE"])
+                    .conflicts_with("languages")
+                    .conflicts_with("show-tree")
+                    .conflicts_with("FORMAT")
+                    .conflicts_with("sort")
+                    .conflicts_with_all(["after-context", "before-context", "context"])
+                    .conflicts_with_all(["count", "count-by"])
+                    .conflicts_with_all(["files-with-matches", "files-without-match"])
+                    .conflicts_with("injections")
+                    .conflicts_with("stats")
+            )
+            .arg(
+                Arg::new("write")
+                    .long("write")
+                    .action(ArgAction::SetTrue)
+                    .help("write rewritten files in place instead of printing a diff")
+                    .requires("rewrite")
+                    .conflicts_with("stdin")
+            )
+            .arg(
+                Arg::new("grammar")
+                    .long("grammar")
+                    .value_name("NAME=PATH")
+                    .help("load the grammar for NAME from a shared library")
+                    .long_help("load the grammar for NAME from a shared library (like the ones `tree-sitter build` makes.) The library needs to have a `tree_sitter_NAME` function in it. PATH can also be a `.wasm` file (from `tree-sitter build --wasm`, if tree-grepper was built with the `wasm` feature), or a checkout of the grammar's repository, in which case it's compiled (and cached until the source changes.) Once it's loaded, you can use NAME anywhere you'd use a built-in language.")
+                    .action(ArgAction::Append)
+            )
+            .arg(
+                Arg::new("grammar-dir")
+                    .long("grammar-dir")
+                    .value_name("DIR")
+                    .help("load every grammar in DIR")
+                    .long_help("load every grammar in DIR. Libraries should be named like `libtree-sitter-NAME.so` (or `.dylib` on macOS, or `tree-sitter-NAME.dll` on Windows), WebAssembly grammars like `tree-sitter-NAME.wasm`, and checkouts like `tree-sitter-NAME`. Defaults to `$XDG_CONFIG_HOME/tree-grepper/grammars` (or `~/.config/tree-grepper/grammars`.)")
+            )
             .arg(
                 Arg::new("languages")
                     .long("languages")
@@ -111,6 +501,7 @@ impl Invocation {
                 Arg::new("show-tree")
                     .long("show-tree")
                     .help("Show the node names and associated text of the specified files")
+                    .long_help("Show the node names and associated text of the specified files. Use `-` as the path to read the source from stdin.")
                     .value_names(["LANGUAGE"])
                     .action(ArgAction::Append)
                     .conflicts_with("languages")
@@ -119,8 +510,13 @@ impl Invocation {
             .try_get_matches_from(args)
             .context("could not parse args")?;
 
+        // grammars have to be loaded before we look up any languages by name
+        Self::load_grammars(&matches)?;
+
         if matches.get_flag("languages") {
             Ok(Self::ShowLanguages)
+        } else if matches.get_flag("type-list") {
+            Ok(Self::ShowTypes(Self::file_types(&matches)?))
         } else if let Some(raw_lang) = matches.get_one::<String>("show-tree") {
             let lang = Language::from_str(raw_lang).context("could not parse language")?;
 
@@ -129,75 +525,272 @@ impl Invocation {
                 anyhow::bail!("need exactly one path to print a tree")
             }
 
+            // like a lot of other tools, we take `-` to mean stdin
+            let input = if paths[0] == Path::new("-") {
+                Input::Stdin(None)
+            } else {
+                Input::File(paths[0].to_owned())
+            };
+
             Ok(Self::ShowTree(TreeOpts {
                 language: lang,
-                path: paths[0].to_owned(),
+                input,
+                encoding: Self::encoding(&matches)?,
+            }))
+        } else if let Some(mut values) = matches.get_many::<String>("rewrite") {
+            let (capture, template) = match (values.next(), values.next()) {
+                (Some(capture), Some(template)) => (capture, template),
+                _ => bail!("a rewrite needs a capture and a template. This indicates an internal error and you should report it!"),
+            };
+            let rewrite = Rewrite::new(capture.to_owned(), template)?;
+
+            // rewrites are always sorted so diffs come out in a stable order,
+            // and stop at the first problem so we never write only some of
+            // them.
+            let searcher = Self::searcher(&matches)?
+                .sort(true)
+                .fail_fast(true)
+                .build()
+                .context("could not build a searcher")?;
+
+            for extractor in searcher.extractors() {
+                let captures = extractor.captures();
+                for name in
+                    std::iter::once(rewrite.capture()).chain(rewrite.template().capture_names())
+                {
+                    if !captures.iter().any(|capture| capture == name) {
+                        bail!(
+                            "the {} query doesn't have a capture named {}. Try one of: {}",
+                            extractor.language(),
+                            name,
+                            captures.join(", ")
+                        )
+                    }
+                }
+            }
+
+            Ok(Self::DoRewrite(RewriteOpts {
+                searcher,
+                rewrite,
+                write: matches.get_flag("write"),
             }))
         } else {
+            let format = QueryFormat::from_str(
+                matches
+                    .get_one::<String>("FORMAT")
+                    .context("format not provided")?,
+            )
+            .context("could not set format")?;
+
+            let json_errors = matches.get_flag("json-errors");
+            if json_errors && matches!(format, QueryFormat::Lines) {
+                bail!(
+                    "--json-errors only works with JSON output. Try adding `--format json-lines`."
+                )
+            }
+
+            let context = matches.get_one::<usize>("context").copied().unwrap_or(0);
+            let before_context = matches
+                .get_one::<usize>("before-context")
+                .copied()
+                .unwrap_or(context);
+            let after_context = matches
+                .get_one::<usize>("after-context")
+                .copied()
+                .unwrap_or(context);
+
             Ok(Self::DoQuery(QueryOpts {
-                extractors: Self::extractors(&matches)?,
-                paths: Self::paths(&matches)?,
-                git_ignore: !matches.contains_id("no-gitignore"),
-                format: QueryFormat::from_str(
+                searcher: Self::searcher(&matches)?
+                    .sort(matches.get_flag("sort"))
+                    .context(before_context, after_context)
+                    .injections(matches.get_flag("injections"))
+                    .build()
+                    .context("could not build a searcher")?,
+                format,
+                count: match matches.get_one::<String>("count-by").map(|by| by.as_str()) {
+                    Some("capture") => Some(CountBy::Capture),
+                    Some(other) => bail!(
+                        "unknown count grouping {}. See --help for valid groupings.",
+                        other
+                    ),
+                    None if matches.get_flag("count") => Some(CountBy::File),
+                    None => None,
+                },
+                list_files: if matches.get_flag("files-with-matches") {
+                    Some(ListFiles::WithMatches)
+                } else if matches.get_flag("files-without-match") {
+                    Some(ListFiles::WithoutMatch)
+                } else {
+                    None
+                },
+                color: ColorChoice::from_str(
                     matches
-                        .get_one::<String>("FORMAT")
-                        .context("format not provided")?,
+                        .get_one::<String>("color")
+                        .context("color not provided")?,
                 )
-                .context("could not set format")?,
-                sort: matches.contains_id("sort"),
+                .context("could not set color")?,
+                heading: matches.get_flag("heading"),
+                hyperlinks: matches
+                    .get_one::<String>("hyperlink-format")
+                    .map(|raw| HyperlinkFormat::parse(raw))
+                    .transpose()
+                    .context("could not set hyperlink format")?,
+                stats: matches.get_flag("stats"),
+                json_errors,
             }))
         }
     }
 
-    fn extractors(matches: &ArgMatches) -> Result<Vec<Extractor>> {
-        let values = match matches.get_many::<String>("additional-query") {
-            Some(values) => values,
-            None => bail!("queries were required but not provided. This indicates an internal error and you should report it!"),
-        };
+    /// The options shared between searching and rewriting.
+    fn searcher(matches: &ArgMatches) -> Result<SearcherBuilder> {
+        let mut builder = Searcher::builder();
 
-        // the most common case is going to be one query, so let's allocate
-        // that immediately...
-        let mut query_strings: HashMap<Language, String> = HashMap::with_capacity(1);
-
-        // If you have two tree-sitter queries `(one)` and `(two)`, you can
-        // join them together in a single string like `(one)(two)`. In that
-        // case, the resulting query will act like an OR and match any of the
-        // queries inside. Doing this automatically gives us an advantage:
-        // for however many queries we get on the command line, we will only
-        // ever have to run one per file, since we can combine them and you
-        // can't specify queries across multiple languages! Nobody should ever
-        // notice, except that they won't see as much of a slowdown for adding
-        // new queries to an invocation as they might expect. (Well, hopefully!)
-        for (raw_lang, raw_query) in values.tuples() {
-            let lang = Language::from_str(raw_lang).context("could not parse language")?;
+        if let Some(values) = matches.get_many::<String>("additional-query") {
+            for (raw_lang, raw_query) in values.tuples() {
+                let lang = Language::from_str(raw_lang).context("could not parse language")?;
+                builder.query(lang, raw_query);
+            }
+        }
 
-            let mut query_out = String::from(raw_query);
+        if let Some(values) = matches.get_many::<String>("query-file") {
+            for (raw_lang, raw_path) in values.tuples() {
+                let lang = Language::from_str(raw_lang).context("could not parse language")?;
+                builder.query_file(lang, raw_path);
+            }
+        }
+
+        if let Some(values) = matches.get_many::<String>("queries-file") {
+            for raw_path in values {
+                builder.queries_file(raw_path);
+            }
+        }
 
-            let temp_query = lang
-                .parse_query(raw_query)
-                .context("could not parse query")?;
+        if let Some(raw_lang) = matches.get_one::<String>("stdin") {
+            builder.stdin(Language::from_str(raw_lang).context("could not parse stdin language")?);
 
-            if temp_query.capture_names().is_empty() {
-                query_out.push_str("@query");
+            if let Some(label) = matches.get_one::<String>("stdin-label") {
+                builder.stdin_label(label);
             }
+        } else {
+            for path in Self::paths(matches)? {
+                builder.path(path);
+            }
+        }
 
-            if let Some(existing) = query_strings.get_mut(&lang) {
-                existing.push_str(&query_out);
-            } else {
-                query_strings.insert(lang, query_out);
+        builder.file_types(Self::file_types(matches)?);
+        builder.git_ignore(!matches.get_flag("no-gitignore"));
+        builder.detect_languages(matches.get_flag("detect-language"));
+        builder.include_generated(matches.get_flag("include-generated"));
+        builder.include_minified(matches.get_flag("include-minified"));
+        builder.explain_skips(matches.get_flag("verbose"));
+        builder.encoding(Self::encoding(matches)?);
+        builder.fail_fast(matches.get_flag("fail-fast"));
+
+        if let Some(timeout) = Self::duration(matches, "parse-timeout")? {
+            builder.parse_timeout(timeout);
+        }
+        if let Some(timeout) = Self::duration(matches, "query-timeout")? {
+            builder.query_timeout(timeout);
+        }
+        if let Some(deadline) = Self::duration(matches, "deadline")? {
+            builder.deadline(deadline);
+        }
+
+        if let Some(raw) = matches.get_one::<String>("max-filesize") {
+            builder.max_filesize(skip::parse_size(raw).context("could not parse --max-filesize")?);
+        }
+        if let Some(within) = Self::duration(matches, "changed-within")? {
+            builder.modified_after(
+                SystemTime::now()
+                    .checked_sub(within)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+            );
+        }
+        if let Some(raw_path) = matches.get_one::<String>("newer-than") {
+            builder.modified_after(
+                fs::metadata(raw_path)
+                    .and_then(|metadata| metadata.modified())
+                    .with_context(|| {
+                        format!("could not get the modification time of {}", raw_path)
+                    })?,
+            );
+        }
+
+        if let Some(threads) = matches.get_one::<usize>("threads") {
+            builder.threads(*threads);
+        }
+
+        Ok(builder)
+    }
+
+    fn load_grammars(matches: &ArgMatches) -> Result<()> {
+        let dir = match matches.get_one::<String>("grammar-dir") {
+            Some(raw_dir) => Some(PathBuf::from(raw_dir)),
+            None => grammar::default_dir(),
+        };
+        if let Some(dir) = dir {
+            for problem in grammar::register_dir(&dir) {
+                eprintln!("Warning: {:#}", problem);
+            }
+        }
+
+        if let Some(values) = matches.get_many::<String>("grammar") {
+            for raw in values {
+                let (name, path) = match raw.split_once('=') {
+                    Some(parts) => parts,
+                    None => bail!("expected --grammar to look like NAME=PATH, but got {}", raw),
+                };
+
+                grammar::register(Grammar::load(name, Path::new(path))?)
+                    .context("could not load grammar")?;
             }
         }
 
-        let mut out = Vec::with_capacity(query_strings.len());
-        for (lang, raw_query) in query_strings {
-            let query = lang
-                .parse_query(&raw_query)
-                .context("could not parse combined query")?;
+        Ok(())
+    }
+
+    fn file_types(matches: &ArgMatches) -> Result<FileTypes> {
+        let mut file_types = FileTypes::new();
+
+        if let Some(values) = matches.get_many::<String>("type-add") {
+            for (raw_lang, glob) in values.tuples() {
+                let lang = Language::from_str(raw_lang).context("could not parse language")?;
+                file_types.add(lang, glob);
+            }
+        }
+
+        if let Some(values) = matches.get_many::<String>("parse-as") {
+            for (glob, raw_lang) in values.tuples() {
+                let lang = Language::from_str(raw_lang).context("could not parse language")?;
+                file_types.parse_as(glob, lang);
+            }
+        }
 
-            out.push(Extractor::new(lang, query))
+        if let Some(raw_lang) = matches.get_one::<String>("header-language") {
+            file_types.parse_headers_as(
+                Language::from_str(raw_lang).context("could not parse header language")?,
+            );
         }
 
-        Ok(out)
+        Ok(file_types)
+    }
+
+    fn encoding(matches: &ArgMatches) -> Result<Encoding> {
+        Encoding::from_str(
+            matches
+                .get_one::<String>("encoding")
+                .context("encoding not provided")?,
+        )
+        .context("could not set encoding")
+    }
+
+    fn duration(matches: &ArgMatches, name: &str) -> Result<Option<Duration>> {
+        matches
+            .get_one::<String>(name)
+            .map(|raw| {
+                limits::parse_duration(raw).with_context(|| format!("could not parse --{}", name))
+            })
+            .transpose()
     }
 
     fn paths(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
@@ -233,3 +826,23 @@ impl FromStr for QueryFormat {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => bail!("unknown color choice. See --help for valid choices."),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::extractor::ExtractedFile;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountBy {
+    File,
+    Capture,
+}
+
+/// How many matches a file had, for when you don't need the matches
+/// themselves.
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileCount {
+    file: Option<PathBuf>,
+    file_type: String,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captures: Option<BTreeMap<String, usize>>,
+}
+
+impl FileCount {
+    pub fn new(extracted_file: &ExtractedFile, by: CountBy) -> FileCount {
+        let captures = match by {
+            CountBy::File => None,
+            CountBy::Capture => {
+                let mut captures = BTreeMap::new();
+                for extracted_match in extracted_file.matches() {
+                    *captures
+                        .entry(extracted_match.name().to_string())
+                        .or_insert(0) += 1;
+                }
+                Some(captures)
+            }
+        };
+
+        FileCount {
+            file: extracted_file.file().map(|path| path.to_owned()),
+            file_type: extracted_file.file_type().to_string(),
+            count: extracted_file.matches().len(),
+            captures,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Counts for each capture name, if they were asked for.
+    pub fn captures(&self) -> Option<&BTreeMap<String, usize>> {
+        self.captures.as_ref()
+    }
+}
+
+impl Display for FileCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // see the note in `LinesPrinter` about unwrapping
+        let filename = self
+            .file
+            .as_ref()
+            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
+            .unwrap_or("NO FILE");
+
+        match &self.captures {
+            None => writeln!(f, "{}:{}", filename, self.count),
+            Some(captures) => {
+                for (name, count) in captures {
+                    writeln!(f, "{}:{}:{}", filename, name, count)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "javascript"))]
+mod tests {
+    use super::*;
+    use crate::extractor::Extractor;
+    use crate::language::Language;
+    use tree_sitter::Parser;
+
+    fn extract(by: CountBy) -> FileCount {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query("(call_expression function: (identifier)@fn) (string)@string")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"a('x'); b('y'); c()", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        FileCount::new(&extracted, by)
+    }
+
+    #[test]
+    fn counts_files() {
+        assert_eq!(extract(CountBy::File).to_string(), "NO FILE:5\n");
+    }
+
+    #[test]
+    fn counts_captures() {
+        assert_eq!(
+            extract(CountBy::Capture).to_string(),
+            "NO FILE:fn:3\nNO FILE:string:2\n"
+        );
+    }
+}
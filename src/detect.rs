@@ -0,0 +1,182 @@
+use crate::language::Language;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How much of a file we read to look for a shebang, a modeline, or a marker
+/// that says it was generated, or to tell that it's binary or minified. This
+/// has to be longer than `skip::MINIFIED_LINE_LEN`.
+const HEAD_LEN: usize = 8 * 1024;
+
+/// How many lines at the top of a file can have a Vim modeline in them. Vim
+/// also looks at the bottom of the file, but we only read the start.
+const MODELINE_LINES: usize = 5;
+
+/// Read the first few bytes of the file at `path`. That's all we need to
+/// tell what it is without parsing it.
+pub fn read_head(path: &Path) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    File::open(path)?
+        .take(HEAD_LEN as u64)
+        .read_to_end(&mut head)?;
+
+    Ok(head)
+}
+
+/// Guess the language of a file from the start of it, using (in order):
+///
+/// - a shebang, like `#!/usr/bin/env python3`
+/// - an Emacs modeline on the first line (or the second, after a shebang),
+///   like `-*- mode: ruby -*-`
+/// - a Vim modeline in the first few lines, like `vim: set ft=javascript:`
+pub fn language_from_head(head: &[u8]) -> Option<Language> {
+    let head = String::from_utf8_lossy(head);
+    let lines: Vec<&str> = head.lines().take(MODELINE_LINES).collect();
+
+    let shebang = lines.first().and_then(|line| line.strip_prefix("#!"));
+    if let Some(language) = shebang.and_then(language_from_shebang) {
+        return Some(language);
+    }
+
+    let emacs_lines = if shebang.is_some() { 2 } else { 1 };
+    lines
+        .iter()
+        .take(emacs_lines)
+        .find_map(|line| emacs_mode(line))
+        .or_else(|| lines.iter().find_map(|line| vim_filetype(line)))
+        .and_then(Language::from_alias)
+}
+
+/// The language for the interpreter in a shebang (without the `#!`.) This
+/// looks through `env`, and ignores versions in interpreter names, so
+/// `/usr/bin/env python3.12` is Python.
+fn language_from_shebang(shebang: &str) -> Option<Language> {
+    let mut words = shebang.split_whitespace();
+    let mut interpreter = program_name(words.next()?);
+
+    if interpreter == "env" {
+        // skip flags like `-S` and variables like `FOO=bar`
+        interpreter =
+            program_name(words.find(|word| !word.starts_with('-') && !word.contains('='))?);
+    }
+
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let name = match interpreter {
+        "python" | "pypy" => "python",
+        "node" | "nodejs" | "bun" => "javascript",
+        "deno" | "ts-node" => "typescript",
+        "ruby" | "jruby" => "ruby",
+        "runghc" | "runhaskell" => "haskell",
+        "pwsh" => "powershell",
+        "rust-script" => "rust",
+        other => other,
+    };
+
+    Language::from_alias(name)
+}
+
+fn program_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The mode in an Emacs modeline, like `ruby` in `# -*- mode: ruby -*-` or
+/// in `# -*- ruby -*-`.
+fn emacs_mode(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (vars, _) = rest.split_once("-*-")?;
+
+    if !vars.contains(':') {
+        return Some(vars.trim());
+    }
+
+    vars.split(';').find_map(|var| {
+        let (name, value) = var.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("mode") {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// The filetype (or syntax) in a Vim modeline, like `javascript` in
+/// `// vim: set ft=javascript:` or `# vi: filetype=ruby`.
+fn vim_filetype(line: &str) -> Option<&str> {
+    let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let start = line.find(marker)?;
+        let before = line[..start].chars().next_back();
+
+        if before.is_none_or(char::is_whitespace) {
+            Some(&line[start + marker.len()..])
+        } else {
+            None
+        }
+    })?;
+
+    options
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|option| {
+            let (name, value) = option.split_once('=')?;
+            match name {
+                "ft" | "filetype" | "syn" | "syntax" => Some(value),
+                _ => None,
+            }
+        })
+}
+
+#[cfg(test)]
+#[cfg_attr(
+    not(any(
+        all(feature = "python", feature = "javascript"),
+        all(feature = "ruby", feature = "cpp"),
+        all(feature = "javascript", feature = "sass")
+    )),
+    allow(dead_code)
+)]
+mod tests {
+    use super::*;
+
+    fn detect(head: &str) -> Option<String> {
+        language_from_head(head.as_bytes()).map(|language| language.to_string())
+    }
+
+    #[test]
+    #[cfg(all(feature = "python", feature = "javascript"))]
+    fn shebangs_name_interpreters() {
+        assert_eq!(detect("#!/usr/bin/python3\n"), Some(String::from("python")));
+        assert_eq!(
+            detect("#!/usr/bin/env python3.12\nimport os\n"),
+            Some(String::from("python"))
+        );
+        assert_eq!(
+            detect("#!/usr/bin/env -S NODE_ENV=production node --harmony\n"),
+            Some(String::from("javascript"))
+        );
+        assert_eq!(detect("#!/bin/sh\n"), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "ruby", feature = "cpp"))]
+    fn emacs_modelines_have_modes() {
+        assert_eq!(detect("# -*- mode: ruby -*-\n"), Some(String::from("ruby")));
+        assert_eq!(
+            detect("#!/bin/sh\n// -*- coding: utf-8; mode: C++ -*-\n"),
+            Some(String::from("cpp"))
+        );
+        assert_eq!(detect("\n\n# -*- ruby -*-\n"), None);
+    }
+
+    #[test]
+    #[cfg(all(feature = "javascript", feature = "sass"))]
+    fn vim_modelines_have_filetypes() {
+        assert_eq!(
+            detect("// hi\n// vim: set ts=2 ft=javascript:\n"),
+            Some(String::from("javascript"))
+        );
+        assert_eq!(
+            detect("/* vi:filetype=scss */\n"),
+            Some(String::from("sass"))
+        );
+        assert_eq!(detect("// nvim: ft=javascript\n"), None);
+    }
+}
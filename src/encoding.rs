@@ -0,0 +1,380 @@
+use anyhow::{bail, Context, Error, Result};
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use tree_sitter::Point;
+
+/// How to turn the bytes in a file into text. Whatever this is, a file that
+/// starts with a byte order mark is read in the encoding the mark is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-8, unless there's a byte order mark that says otherwise. Bytes
+    /// that aren't valid UTF-8 are parsed as they are, and show up as `�` in
+    /// output.
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the character with the same number.
+    Latin1,
+    /// Latin-1 with printable characters (like `€` and curly quotes) where
+    /// Latin-1 has control characters, from 0x80 to 0x9F.
+    Windows1252,
+}
+
+impl Encoding {
+    /// Turn `bytes` into UTF-8 so we can parse it, remembering where
+    /// everything came from.
+    pub fn decode(self, bytes: Vec<u8>) -> Decoded {
+        let (encoding, bom) = match bytes.as_slice() {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+            _ if self == Encoding::Auto => (Encoding::Utf8, 0),
+            _ => (self, 0),
+        };
+
+        let body = &bytes[bom..];
+        let ((text, offsets), lossy) = match encoding {
+            Encoding::Utf16Le => transcode_utf16(body, u16::from_le_bytes),
+            Encoding::Utf16Be => transcode_utf16(body, u16::from_be_bytes),
+            Encoding::Latin1 if !body.is_ascii() => (transcode_bytes(body, char::from), false),
+            Encoding::Windows1252 if !body.is_ascii() => {
+                (transcode_bytes(body, windows_1252), false)
+            }
+            // ASCII is the same in all of the single-byte encodings, so
+            // there's nothing to do.
+            _ if bom == 0 => {
+                return Decoded {
+                    text: bytes,
+                    encoding,
+                    bom,
+                    offsets: None,
+                    lossy: false,
+                }
+            }
+            _ => ((body.to_vec(), None), false),
+        };
+
+        Decoded {
+            text,
+            encoding,
+            bom,
+            offsets,
+            lossy,
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Encoding::Auto),
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            _ => bail!("unknown encoding {}. See --help for valid encodings.", s),
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Auto => "auto",
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Windows1252 => "windows-1252",
+        })
+    }
+}
+
+/// A file's source as UTF-8 (or as close as it gets), and how to get back to
+/// positions in the bytes it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    text: Vec<u8>,
+    encoding: Encoding,
+    bom: usize,
+    // where each byte of `text` (plus the end of it) starts in the original
+    // bytes, after the byte order mark. `None` means they're the same.
+    offsets: Option<Vec<usize>>,
+    // whether some of the original bytes had to be replaced with `�`, so
+    // `text` can't be turned back into them.
+    lossy: bool,
+}
+
+impl Decoded {
+    /// The text to parse. This is UTF-8 unless the file was supposed to be
+    /// and wasn't.
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    /// The encoding the file was read as. This is never `Auto`.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Where `byte` in `text` is in the original bytes.
+    pub fn original_byte(&self, byte: usize) -> usize {
+        self.bom
+            + match &self.offsets {
+                Some(offsets) => offsets[byte],
+                None => byte,
+            }
+    }
+
+    /// Where `point` (at `byte` in `text`) is in the original bytes. Rows
+    /// don't change, but columns count the original bytes from the start of
+    /// the line. The byte order mark isn't part of the first line.
+    pub fn original_point(&self, byte: usize, point: Point) -> Point {
+        if self.offsets.is_none() {
+            return point;
+        }
+
+        Point {
+            row: point.row,
+            column: self.original_byte(byte) - self.original_byte(byte - point.column),
+        }
+    }
+
+    /// Turn `text` (like a rewritten version of ours) back into bytes in the
+    /// original encoding, with the same byte order mark. This fails if the
+    /// original bytes weren't valid in their encoding (like unpaired UTF-16
+    /// surrogates), since writing `text` back would replace them with `�`.
+    pub fn encode(&self, text: &[u8]) -> Result<Vec<u8>> {
+        if self.lossy {
+            bail!(
+                "the original wasn't valid {}, so writing it back would change bytes that weren't rewritten",
+                self.encoding
+            );
+        }
+
+        let mut out = Vec::with_capacity(self.bom + text.len());
+        if self.bom > 0 {
+            out.extend_from_slice(match self.encoding {
+                Encoding::Utf16Le => &[0xFF, 0xFE],
+                Encoding::Utf16Be => &[0xFE, 0xFF],
+                _ => &[0xEF, 0xBB, 0xBF],
+            });
+        }
+
+        // we can't go by whether the text was transcoded on the way in: ASCII
+        // files in a single-byte encoding weren't, but new text (like a
+        // rewrite) might not be ASCII.
+        let text = match self.encoding {
+            Encoding::Auto | Encoding::Utf8 => {
+                out.extend_from_slice(text);
+                return Ok(out);
+            }
+            _ => std::str::from_utf8(text).context("could not encode text that isn't UTF-8")?,
+        };
+        for c in text.chars() {
+            match self.encoding {
+                Encoding::Utf16Le => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.extend_from_slice(&unit.to_le_bytes())
+                    }
+                }
+                Encoding::Utf16Be => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.extend_from_slice(&unit.to_be_bytes())
+                    }
+                }
+                Encoding::Latin1 => match u8::try_from(c) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => bail!("{} can't be written in {}", c, self.encoding),
+                },
+                _ => match (0..=255).find(|byte| windows_1252(*byte) == c) {
+                    Some(byte) => out.push(byte),
+                    None => bail!("{} can't be written in {}", c, self.encoding),
+                },
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Like `transcode_bytes`, but also says whether anything couldn't be
+/// decoded and was replaced with `�`.
+fn transcode_utf16(
+    bytes: &[u8],
+    unit: fn([u8; 2]) -> u16,
+) -> ((Vec<u8>, Option<Vec<usize>>), bool) {
+    let mut text = Vec::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(bytes.len() + 1);
+    let mut position = 0;
+    let mut lossy = false;
+
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    for result in char::decode_utf16(units) {
+        let (c, width) = match result {
+            Ok(c) => (c, c.len_utf16() * 2),
+            // an unpaired surrogate
+            Err(_) => {
+                lossy = true;
+                (char::REPLACEMENT_CHARACTER, 2)
+            }
+        };
+        push_char(&mut text, &mut offsets, c, position);
+        position += width;
+    }
+
+    // an odd byte at the end can't be a whole character
+    if position < bytes.len() {
+        lossy = true;
+        push_char(
+            &mut text,
+            &mut offsets,
+            char::REPLACEMENT_CHARACTER,
+            position,
+        );
+    }
+
+    offsets.push(bytes.len());
+    ((text, Some(offsets)), lossy)
+}
+
+fn transcode_bytes(bytes: &[u8], decode: fn(u8) -> char) -> (Vec<u8>, Option<Vec<usize>>) {
+    let mut text = Vec::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(bytes.len() + 1);
+
+    for (position, byte) in bytes.iter().enumerate() {
+        push_char(&mut text, &mut offsets, decode(*byte), position);
+    }
+
+    offsets.push(bytes.len());
+    (text, Some(offsets))
+}
+
+fn push_char(text: &mut Vec<u8>, offsets: &mut Vec<usize>, c: char, position: usize) {
+    let mut buffer = [0; 4];
+    let encoded = c.encode_utf8(&mut buffer);
+    text.extend_from_slice(encoded.as_bytes());
+    offsets.extend(std::iter::repeat_n(position, encoded.len()));
+}
+
+/// The characters Windows-1252 has in place of Latin-1's control characters.
+/// The five bytes it leaves undefined stay control characters, like in web
+/// browsers.
+fn windows_1252(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž',
+        '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}',
+        'ž', 'Ÿ',
+    ];
+
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => char::from(byte),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_left_alone() {
+        let decoded = Encoding::Auto.decode(b"caf\xC3\xA9 \xFF".to_vec());
+
+        assert_eq!(decoded.text(), b"caf\xC3\xA9 \xFF");
+        assert_eq!(decoded.encoding(), Encoding::Utf8);
+        assert_eq!(decoded.original_byte(6), 6);
+    }
+
+    #[test]
+    fn byte_order_marks_win() {
+        let decoded = Encoding::Latin1.decode(b"\xFF\xFEh\x00\xE9\x00".to_vec());
+
+        assert_eq!(decoded.text(), "hé".as_bytes());
+        assert_eq!(decoded.encoding(), Encoding::Utf16Le);
+        assert_eq!(decoded.original_byte(1), 4);
+        assert_eq!(decoded.original_byte(3), 6);
+
+        let decoded = Encoding::Auto.decode(b"\xEF\xBB\xBFfn".to_vec());
+        assert_eq!(decoded.text(), b"fn");
+        assert_eq!(decoded.original_byte(1), 4);
+    }
+
+    #[test]
+    fn positions_point_into_the_original_bytes() {
+        let decoded = Encoding::Latin1.decode(b"\xE9\n\xE9\xE9 x".to_vec());
+
+        assert_eq!(decoded.text(), "é\néé x".as_bytes());
+        // `x` is at byte 8 of the text, on the second line
+        assert_eq!(
+            decoded.original_point(8, Point { row: 1, column: 5 }),
+            Point { row: 1, column: 3 }
+        );
+    }
+
+    #[test]
+    fn windows_1252_has_curly_quotes() {
+        let decoded = Encoding::Windows1252.decode(b"\x93hi\x94 \x80".to_vec());
+
+        assert_eq!(decoded.text(), "“hi” €".as_bytes());
+    }
+
+    #[test]
+    fn encoding_goes_back_to_the_original_bytes() {
+        for original in [
+            b"\xFE\xFF\x00h\x00\xE9".to_vec(),
+            b"\xEF\xBB\xBFcaf\xC3\xA9".to_vec(),
+            b"\xE9t\xE9".to_vec(),
+            b"plain".to_vec(),
+        ] {
+            let encoding = if original.starts_with(b"\xFE") || original.starts_with(b"\xEF") {
+                Encoding::Auto
+            } else {
+                Encoding::Latin1
+            };
+            let decoded = encoding.decode(original.clone());
+
+            assert_eq!(decoded.encode(decoded.text()).unwrap(), original);
+        }
+
+        let decoded = Encoding::Latin1.decode(b"\xE9".to_vec());
+        assert!(decoded.encode("€".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn invalid_utf16_is_not_written_back() {
+        // an unpaired surrogate, and an odd byte at the end
+        for original in [
+            b"\xFF\xFEh\x00\x00\xD8".to_vec(),
+            b"\xFF\xFEh\x00i".to_vec(),
+        ] {
+            let decoded = Encoding::Auto.decode(original);
+
+            assert_eq!(decoded.text(), "h\u{FFFD}".as_bytes());
+            assert!(decoded.encode(decoded.text()).is_err());
+        }
+    }
+
+    #[test]
+    fn rewritten_ascii_stays_in_the_original_encoding() {
+        let decoded = Encoding::Latin1.decode(b"name = 'cafe'".to_vec());
+        let rewritten = crate::rewriter::apply_edits(
+            None,
+            decoded.text(),
+            vec![crate::rewriter::Edit {
+                bytes: 8..12,
+                start: Point { row: 0, column: 8 },
+                replacement: "café".as_bytes().to_vec(),
+            }],
+        )
+        .unwrap();
+
+        assert_eq!(decoded.encode(&rewritten).unwrap(), b"name = 'caf\xE9'");
+    }
+}
@@ -1,12 +1,20 @@
+use crate::encoding::Decoded;
+use crate::grammar;
 use crate::language::Language;
-use anyhow::{Context, Result};
+use crate::limits::{Limits, Stage};
+use crate::printer::LinesPrinter;
+use crate::rewriter::{Edit, Rewrite};
+use anyhow::{bail, Context, Result};
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display};
 use std::fs;
 use std::path::{Path, PathBuf};
-use tree_sitter::{Parser, Point, Query, QueryCursor, StreamingIterator};
+use tree_sitter::{
+    ParseOptions, ParseState, Parser, Point, Query, QueryCursor, QueryCursorOptions,
+    QueryCursorState, QueryMatches, Range, StreamingIterator, Tree,
+};
 
 #[derive(Debug)]
 pub struct Extractor {
@@ -15,6 +23,10 @@ pub struct Extractor {
     query: Query,
     captures: Vec<String>,
     ignores: HashSet<usize>,
+    before_context: usize,
+    after_context: usize,
+    limits: Limits,
+    injections_only: bool,
 }
 
 impl Extractor {
@@ -42,45 +54,143 @@ impl Extractor {
             query,
             captures,
             ignores,
+            before_context: 0,
+            after_context: 0,
+            limits: Limits::default(),
+            injections_only: false,
         }
     }
 
+    /// An extractor that never matches anything. The searcher uses these to
+    /// look for injections in files that nobody asked about directly.
+    pub fn empty(language: Language) -> Result<Extractor> {
+        let query = language
+            .parse_query("")
+            .with_context(|| format!("could not make an empty {} query", language))?;
+
+        Ok(Extractor {
+            ts_language: language.language(),
+            language,
+            query,
+            captures: Vec::new(),
+            ignores: HashSet::default(),
+            before_context: 0,
+            after_context: 0,
+            limits: Limits::default(),
+            injections_only: true,
+        })
+    }
+
+    /// Include this many lines of source before and after each match.
+    pub fn with_context(self, before: usize, after: usize) -> Extractor {
+        Extractor {
+            before_context: before,
+            after_context: after,
+            ..self
+        }
+    }
+
+    /// Stop parsing or querying when it takes too long. When that happens,
+    /// we give an error with `TimedOut` in its chain.
+    pub fn with_limits(self, limits: Limits) -> Extractor {
+        Extractor { limits, ..self }
+    }
+
     pub fn language(&self) -> &Language {
         &self.language
     }
 
+    /// Whether this extractor (made with `empty`) is only here to find
+    /// injections, not because anyone asked about its language.
+    pub fn injections_only(&self) -> bool {
+        self.injections_only
+    }
+
+    pub fn captures(&self) -> &[String] {
+        &self.captures
+    }
+
     pub fn extract_from_file(
         &self,
         path: &Path,
         parser: &mut Parser,
-    ) -> Result<Option<ExtractedFile>> {
+    ) -> Result<Option<ExtractedFile<'_>>> {
         let source = fs::read(path).context("could not read file")?;
 
         self.extract_from_text(Some(path), &source, parser)
     }
 
+    /// Check whether the file has at least one match, without extracting
+    /// any of them. This stops at the first capture we would have reported.
+    pub fn has_match_in_file(&self, path: &Path, parser: &mut Parser) -> Result<bool> {
+        let source = fs::read(path).context("could not read file")?;
+
+        self.has_match_in_text(&source, parser)
+    }
+
+    pub fn has_match_in_text(&self, source: &[u8], parser: &mut Parser) -> Result<bool> {
+        let tree = self.parse(source, parser)?;
+
+        self.has_match_in_tree(source, &tree)
+    }
+
+    /// Like `has_match_in_text`, but for a tree you've already parsed (with
+    /// `parse` or `parse_ranges`.)
+    pub fn has_match_in_tree(&self, source: &[u8], tree: &Tree) -> Result<bool> {
+        let watch = self.limits.watch(Stage::Query);
+        let mut progress = |_: &QueryCursorState| watch.check();
+        let mut cursor = QueryCursor::new();
+        let mut matches = self.matches(&mut cursor, tree, source, &mut progress);
+
+        while let Some(match_) = matches.next() {
+            if match_
+                .captures
+                .iter()
+                .any(|capture| !self.ignores.contains(&(capture.index as usize)))
+            {
+                return Ok(true);
+            }
+        }
+
+        watch.finish()?;
+        Ok(false)
+    }
+
     pub fn extract_from_text(
         &self,
         path: Option<&Path>,
         source: &[u8],
         parser: &mut Parser,
-    ) -> Result<Option<ExtractedFile>> {
-        parser
-            .set_language(&self.ts_language)
-            .context("could not set language")?;
-
-        let tree = parser
-            .parse(source, None)
-            // note: this could be a timeout or cancellation, but we don't set
-            // that so we know it's always a language error. Buuuut we also
-            // always set the language above so if this happens we also know
-            // it's an internal error.
-            .context(
-                "could not parse to a tree. This is an internal error and should be reported.",
-            )?;
+    ) -> Result<Option<ExtractedFile<'_>>> {
+        let tree = self.parse(source, parser)?;
+
+        self.extract_from_tree(path, source, &tree)
+    }
 
+    /// Like `extract_from_text`, but for a tree you've already parsed (with
+    /// `parse` or `parse_ranges`.) `source` has to be the whole source the
+    /// tree was parsed from.
+    pub fn extract_from_tree(
+        &self,
+        path: Option<&Path>,
+        source: &[u8],
+        tree: &Tree,
+    ) -> Result<Option<ExtractedFile<'_>>> {
+        let watch = self.limits.watch(Stage::Query);
+        let mut progress = |_: &QueryCursorState| watch.check();
         let mut cursor = QueryCursor::new();
-        let mut matches = cursor.matches(&self.query, tree.root_node(), source);
+        let mut matches = self.matches(&mut cursor, tree, source, &mut progress);
+
+        let lines: Option<Vec<&[u8]>> = if self.before_context > 0 || self.after_context > 0 {
+            let mut lines: Vec<&[u8]> = source.split(|byte| *byte == b'\n').collect();
+            // a trailing newline ends the last line instead of starting a new one
+            if source.ends_with(b"\n") {
+                lines.pop();
+            }
+            Some(lines)
+        } else {
+            None
+        };
 
         let mut extracted_matches = Vec::new();
         while let Some(match_) = matches.next() {
@@ -91,20 +201,36 @@ impl Extractor {
 
                 let name = &self.captures[capture.index as usize];
                 let node = capture.node;
-                let text = node
-                    .utf8_text(source)
-                    .map(|unowned| unowned.to_string())
-                    .context("could not extract text from capture")?;
+                // invalid UTF-8 shouldn't stop a search, so it shows up as
+                // `�` instead
+                let text = String::from_utf8_lossy(&source[node.byte_range()]).into_owned();
+
+                let start = node.start_position();
+                let end = node.end_position();
+
+                let context = lines.as_ref().map(|lines| {
+                    let first = start.row.saturating_sub(self.before_context);
+                    let last = (end.row + self.after_context + 1).min(lines.len());
+
+                    (first..last)
+                        .map(|row| ContextLine::new(row, lines[row]))
+                        .collect()
+                });
 
                 extracted_matches.push(ExtractedMatch {
                     kind: node.kind(),
                     name,
                     text,
-                    start: node.start_position(),
-                    end: node.end_position(),
+                    cell: None,
+                    start,
+                    end,
+                    start_byte: node.start_byte(),
+                    end_byte: node.end_byte(),
+                    context,
                 })
             }
         }
+        watch.finish()?;
 
         if extracted_matches.is_empty() {
             Ok(None)
@@ -116,6 +242,144 @@ impl Extractor {
             }))
         }
     }
+
+    pub fn edits_for_text(
+        &self,
+        source: &[u8],
+        parser: &mut Parser,
+        rewrite: &Rewrite,
+    ) -> Result<Vec<Edit>> {
+        let target = match self.query.capture_index_for_name(rewrite.capture()) {
+            Some(index) => index,
+            None => bail!(
+                "the {} query doesn't have a capture named {}",
+                self.language,
+                rewrite.capture()
+            ),
+        };
+
+        let tree = self.parse(source, parser)?;
+
+        let watch = self.limits.watch(Stage::Query);
+        let mut progress = |_: &QueryCursorState| watch.check();
+        let mut cursor = QueryCursor::new();
+        let mut matches = self.matches(&mut cursor, &tree, source, &mut progress);
+
+        let mut edits = Vec::new();
+        while let Some(match_) = matches.next() {
+            // a quantified capture (like `(_)* @args`) can capture several
+            // nodes in one match. We treat those as the whole span from the
+            // first node to the last.
+            let mut spans: HashMap<u32, (usize, usize, Point)> = HashMap::new();
+            for capture in match_.captures {
+                let node = capture.node;
+                spans
+                    .entry(capture.index)
+                    .and_modify(|(start, end, start_point)| {
+                        if node.start_byte() < *start {
+                            *start = node.start_byte();
+                            *start_point = node.start_position();
+                        }
+                        *end = (*end).max(node.end_byte());
+                    })
+                    .or_insert((node.start_byte(), node.end_byte(), node.start_position()));
+            }
+
+            let (start, end, start_point) = match spans.get(&target) {
+                Some(span) => *span,
+                None => continue,
+            };
+
+            let mut texts = HashMap::with_capacity(spans.len());
+            for (index, (start, end, _)) in &spans {
+                texts.insert(
+                    self.captures[*index as usize].as_str(),
+                    &source[*start..*end],
+                );
+            }
+
+            edits.push(Edit {
+                bytes: start..end,
+                start: start_point,
+                replacement: rewrite.template().render(&texts),
+            })
+        }
+        watch.finish()?;
+
+        Ok(edits)
+    }
+
+    /// Parse the whole source with this extractor's language.
+    pub fn parse(&self, source: &[u8], parser: &mut Parser) -> Result<Tree> {
+        self.parse_ranges(source, &[], parser)
+    }
+
+    /// Parse only some parts of `source`, as if they were one document. This
+    /// is how we parse injections: positions in the tree are still positions
+    /// in `source`. An empty list of ranges means the whole source.
+    pub fn parse_ranges(
+        &self,
+        source: &[u8],
+        ranges: &[Range],
+        parser: &mut Parser,
+    ) -> Result<Tree> {
+        grammar::set_parser_language(parser, &self.ts_language)?;
+
+        // parsers get reused between files, so we always set this, even if
+        // it's back to the whole file.
+        parser
+            .set_included_ranges(ranges)
+            .context("could not set the ranges to parse")?;
+
+        let watch = self.limits.watch(Stage::Parse);
+        let mut progress = |_: &ParseState| watch.check();
+        let options = if self.limits.is_unlimited(Stage::Parse) {
+            None
+        } else {
+            Some(ParseOptions::new().progress_callback(&mut progress))
+        };
+
+        let tree = parser.parse_with_options(
+            &mut |byte, _| source.get(byte..).unwrap_or_default(),
+            None,
+            options,
+        );
+        if let Err(timed_out) = watch.finish() {
+            // a cancelled parse picks up where it left off next time, but
+            // the next time is going to be a different file.
+            parser.reset();
+            return Err(timed_out.into());
+        }
+
+        // note: this could also be a cancellation, but we only cancel
+        // through the watch above. We always set the language too, so if
+        // this happens we know it's an internal error.
+        tree.context("could not parse to a tree. This is an internal error and should be reported.")
+    }
+
+    // tree-sitter only borrows `progress` while the matches run, so it has to
+    // outlive them.
+    fn matches<'query, 'tree, 'source, F>(
+        &'query self,
+        cursor: &'query mut QueryCursor,
+        tree: &'tree Tree,
+        source: &'source [u8],
+        progress: &'query mut F,
+    ) -> QueryMatches<'query, 'tree, &'source [u8], &'source [u8]>
+    where
+        F: FnMut(&QueryCursorState) -> bool,
+    {
+        if self.limits.is_unlimited(Stage::Query) {
+            cursor.matches(&self.query, tree.root_node(), source)
+        } else {
+            cursor.matches_with_options(
+                &self.query,
+                tree.root_node(),
+                source,
+                QueryCursorOptions::new().progress_callback(progress),
+            )
+        }
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -125,31 +389,50 @@ pub struct ExtractedFile<'query> {
     matches: Vec<ExtractedMatch<'query>>,
 }
 
-impl Display for ExtractedFile<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // TODO: is there a better way to do this unwrapping? This implementation
-        // turns non-UTF-8 paths into "NON-UTF8 FILENAME". I don't know exactly
-        // what circumstances that could happen in... maybe we should just wait
-        // for bug reports?
-        let filename = self
-            .file
-            .as_ref()
-            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
-            .unwrap_or("NO FILE");
-
-        for extraction in &self.matches {
-            writeln!(
-                f,
-                "{}:{}:{}:{}:{}",
-                filename,
-                extraction.start.row + 1,
-                extraction.start.column + 1,
-                extraction.name,
-                extraction.text
-            )?
+impl<'query> ExtractedFile<'query> {
+    /// The file the matches came from, if they came from a file.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The name of the language the file was parsed as.
+    pub fn file_type(&self) -> &str {
+        &self.file_type
+    }
+
+    pub fn matches(&self) -> &[ExtractedMatch<'query>] {
+        &self.matches
+    }
+
+    /// Add the matches from `other` (for example from an injection in the
+    /// same file) and put everything back in the order it appears in the
+    /// file.
+    pub(crate) fn merge(&mut self, other: ExtractedFile<'query>) {
+        self.matches.extend(other.matches);
+        self.matches
+            .sort_by_key(|found| (found.cell, found.start.row, found.start.column));
+    }
+
+    /// Move every match from where it is in `source`'s text to where it is
+    /// in the bytes `source` was decoded from.
+    pub(crate) fn locate_in(&mut self, source: &Decoded) {
+        for found in &mut self.matches {
+            found.start = source.original_point(found.start_byte, found.start);
+            found.end = source.original_point(found.end_byte, found.end);
+        }
+    }
+
+    /// Mark every match as coming from a notebook cell.
+    pub(crate) fn set_cell(&mut self, cell: usize) {
+        for found in &mut self.matches {
+            found.cell = Some(cell);
         }
+    }
+}
 
-        Ok(())
+impl Display for ExtractedFile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        LinesPrinter::default().display(self).fmt(f)
     }
 }
 
@@ -158,10 +441,85 @@ pub struct ExtractedMatch<'query> {
     kind: &'static str,
     name: &'query str,
     text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cell: Option<usize>,
     #[serde(serialize_with = "serialize_point")]
     start: Point,
     #[serde(serialize_with = "serialize_point")]
     end: Point,
+    #[serde(skip)]
+    start_byte: usize,
+    #[serde(skip)]
+    end_byte: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Vec<ContextLine>>,
+}
+
+impl<'query> ExtractedMatch<'query> {
+    /// The kind of the captured node, like `call_expression`.
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    /// The name of the capture in the query (without the `@`.)
+    pub fn name(&self) -> &'query str {
+        self.name
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The notebook cell the match is in, starting at 1. Positions are
+    /// relative to the start of the cell. This is `None` outside of
+    /// notebooks.
+    pub fn cell(&self) -> Option<usize> {
+        self.cell
+    }
+
+    /// Where the match starts. Note that rows and columns start at 0 here,
+    /// even though we print them starting at 1.
+    pub fn start(&self) -> Point {
+        self.start
+    }
+
+    /// Where the match ends. Rows and columns start at 0, like `start`.
+    pub fn end(&self) -> Point {
+        self.end
+    }
+
+    /// Lines around the match, if context was asked for.
+    pub fn context(&self) -> Option<&[ContextLine]> {
+        self.context.as_deref()
+    }
+}
+
+/// A line of source around a match. Rows start at 1, like the rest of our
+/// output.
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ContextLine {
+    row: usize,
+    text: String,
+}
+
+impl ContextLine {
+    /// The row of the line, starting at 1.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn new(row: usize, line: &[u8]) -> ContextLine {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        ContextLine {
+            row: row + 1,
+            text: String::from_utf8_lossy(line).into_owned(),
+        }
+    }
 }
 
 fn serialize_point<S>(point: &Point, sz: S) -> Result<S::Ok, S::Error>
@@ -175,12 +533,15 @@ where
 }
 
 #[cfg(test)]
+#[cfg_attr(
+    not(any(feature = "elm", feature = "javascript")),
+    allow(unused_imports)
+)]
 mod tests {
     use super::*;
-    use crate::language::Language;
-    use tree_sitter::Parser;
 
     #[test]
+    #[cfg(feature = "elm")]
     fn test_matches_are_extracted() {
         let lang = Language::Elm;
         let query = lang
@@ -201,6 +562,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn test_underscore_names_are_ignored() {
         let lang = Language::Elm;
         let query = lang
@@ -217,6 +579,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "javascript")]
     fn test_underscore_names_can_still_be_used_in_matchers() {
         let lang = Language::JavaScript;
         let query = lang
@@ -235,4 +598,83 @@ mod tests {
         assert_eq!(extracted.matches[0].name, "import");
         assert_eq!(extracted.matches[0].text, "\"foo.js\"");
     }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_edits_use_other_captures() {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query("(call_expression function: (identifier)@fn arguments: (arguments (identifier)@arg))@call")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+        let rewrite = Rewrite::new(String::from("call"), "{fn}({arg}, ctx)").unwrap();
+
+        let edits = extractor
+            .edits_for_text(b"foo(a)", &mut Parser::new(), &rewrite)
+            .unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].bytes, 0..6);
+        assert_eq!(edits[0].replacement, b"foo(a, ctx)");
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_edits_need_the_target_capture() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(identifier)@id").unwrap();
+        let extractor = Extractor::new(lang, query);
+        let rewrite = Rewrite::new(String::from("nope"), "x").unwrap();
+
+        assert!(extractor
+            .edits_for_text(b"foo", &mut Parser::new(), &rewrite)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_context_lines_are_merged_and_separated() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let extractor = Extractor::new(lang, query).with_context(1, 1);
+
+        let extracted = extractor
+            .extract_from_text(
+                None,
+                b"// 1\na();\nb();\n// 4\n// 5\n// 6\nc();\n",
+                &mut Parser::new(),
+            )
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        assert_eq!(
+            extracted.to_string(),
+            "NO FILE-1-// 1\n\
+             NO FILE:2:1:call:a()\n\
+             NO FILE:3:1:call:b()\n\
+             NO FILE-4-// 4\n\
+             --\n\
+             NO FILE-6-// 6\n\
+             NO FILE:7:1:call:c()\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn test_has_match_ignores_underscore_names() {
+        let lang = Language::JavaScript;
+        let query = lang
+            .parse_query("(call_expression (identifier)@_fn (arguments . (string)@import .) (#eq? @_fn require))")
+            .unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        assert!(extractor
+            .has_match_in_text(b"require(\"foo.js\")", &mut Parser::new())
+            .unwrap());
+        assert!(!extractor
+            .has_match_in_text(b"load(\"foo.js\")", &mut Parser::new())
+            .unwrap());
+    }
 }
@@ -1,27 +1,73 @@
+use crate::detect;
 use crate::extractor::Extractor;
+use crate::file_types::FileTypes;
+use crate::generated;
+use crate::gitattributes::{GitAttributes, Value};
+use crate::language::Language;
+use crate::skip::{self, SkipReason};
 use anyhow::{bail, Context, Result};
-use ignore::types::{Types, TypesBuilder};
+use ignore::types::Types;
 use ignore::DirEntry;
+use std::cell::OnceCell;
 use std::collections::HashMap;
+use std::time::SystemTime;
 
 pub struct ExtractorChooser<'extractor> {
-    matcher: Types,
+    // one matcher for each extractor, since a file can match more than one
+    // of them (`.h` files could be C, C++, or CUDA.) They're sorted by
+    // language name, so files are always searched in the same order.
+    matchers: Vec<(Types, &'extractor Extractor)>,
     extractors: HashMap<&'extractor str, &'extractor Extractor>,
+    header_language: Option<Language>,
+    // only there when we're detecting languages from what's in files. It
+    // matches every glob we know about, so we only look inside files that
+    // none of them match.
+    all_types: Option<Types>,
+    attributes: GitAttributes,
+    skip_generated: bool,
+    skip_binary: bool,
+    skip_minified: bool,
+    max_filesize: Option<u64>,
+    modified_after: Option<SystemTime>,
 }
 
-impl ExtractorChooser<'_> {
-    pub fn from_extractors(extractors: &[Extractor]) -> Result<ExtractorChooser> {
-        let mut types_builder = TypesBuilder::new();
-        types_builder.add_defaults();
-        types_builder.add_def("cuda:*.cu,*.cuh,*.hpp")?;
-        types_builder.add_def("cuda:include:cpp")?;
-        types_builder.add_def("powershell:*.ps1")?;
+/// What to do with a file we found while walking.
+#[derive(Debug, Clone)]
+pub enum Choice<'extractor> {
+    /// Search the file with each of these extractors. There's always at
+    /// least one.
+    Search(Vec<&'extractor Extractor>),
+    /// We'd have searched the file, but something about it says we
+    /// shouldn't.
+    Skipped(SkipReason),
+    /// We don't have an extractor for the file (or it's a directory.)
+    Skip,
+}
+
+impl<'extractor> ExtractorChooser<'extractor> {
+    pub fn from_extractors(
+        extractors: &'extractor [Extractor],
+        file_types: &FileTypes,
+    ) -> Result<ExtractorChooser<'extractor>> {
+        let definitions = file_types.types_builder()?.definitions();
 
+        let mut matchers = Vec::with_capacity(extractors.len());
         let mut names_to_extractors = HashMap::with_capacity(extractors.len());
 
         for extractor in extractors {
             let name = extractor.language().name_for_types_builder();
-            types_builder.select(name);
+            if !definitions.iter().any(|def| def.name() == name) {
+                bail!(
+                    "I don't know which files to parse as {}. Add some globs for it with --type-add.",
+                    extractor.language()
+                )
+            }
+            let matcher = file_types
+                .types_builder()?
+                .select(name)
+                .build()
+                .with_context(|| format!("could not build a filetype matcher for {}", name))?;
+            matchers.push((matcher, extractor));
 
             // a little reminder: insert returns the old value if the key was
             // already present
@@ -30,26 +76,218 @@ impl ExtractorChooser<'_> {
             }
         }
 
+        matchers.sort_by_key(|(_, extractor)| extractor.language().to_string());
+
         Ok(ExtractorChooser {
-            matcher: types_builder
-                .build()
-                .context("could not build a filetype matcher using provided extractors")?,
+            matchers,
             extractors: names_to_extractors,
+            header_language: file_types.header_language().cloned(),
+            all_types: None,
+            attributes: GitAttributes::new(),
+            skip_generated: false,
+            skip_binary: false,
+            skip_minified: false,
+            max_filesize: None,
+            modified_after: None,
         })
     }
 
-    pub fn extractor_for(&self, entry: &DirEntry) -> Option<&Extractor> {
-        let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(true);
-        let matched = self.matcher.matched(entry.path(), is_dir);
+    /// Also choose extractors for files by what's in them: a
+    /// `linguist-language` attribute in `.gitattributes` wins over globs, and
+    /// files that no glob matches get their first few bytes checked for a
+    /// shebang or an editor modeline. See the `detect` module for details.
+    pub fn with_detection(
+        self,
+        file_types: &FileTypes,
+        yes: bool,
+    ) -> Result<ExtractorChooser<'extractor>> {
+        let all_types = if yes {
+            Some(
+                file_types
+                    .types_builder()?
+                    .select("all")
+                    .build()
+                    .context("could not build a filetype matcher for every language")?,
+            )
+        } else {
+            None
+        };
+
+        Ok(ExtractorChooser { all_types, ..self })
+    }
+
+    /// Skip generated and vendored code, as marked in `.gitattributes` or by
+    /// a comment at the top of the file. See the `generated` module for
+    /// details. Files you asked for by name are never skipped.
+    pub fn skip_generated(self, yes: bool) -> ExtractorChooser<'extractor> {
+        ExtractorChooser {
+            skip_generated: yes,
+            ..self
+        }
+    }
+
+    /// Skip files that look binary. See `skip::is_binary`. Like generated
+    /// code, files you asked for by name are never skipped.
+    pub fn skip_binary(self, yes: bool) -> ExtractorChooser<'extractor> {
+        ExtractorChooser {
+            skip_binary: yes,
+            ..self
+        }
+    }
 
-        if !matched.is_whitelist() {
+    /// Skip files that look minified. See `skip::is_minified`. Files you asked
+    /// for by name are never skipped.
+    pub fn skip_minified(self, yes: bool) -> ExtractorChooser<'extractor> {
+        ExtractorChooser {
+            skip_minified: yes,
+            ..self
+        }
+    }
+
+    /// Skip files bigger than this many bytes, even if you asked for them by
+    /// name.
+    pub fn max_filesize(self, max: Option<u64>) -> ExtractorChooser<'extractor> {
+        ExtractorChooser {
+            max_filesize: max,
+            ..self
+        }
+    }
+
+    /// Skip files that were last modified at or before this time, even if
+    /// you asked for them by name.
+    pub fn modified_after(self, time: Option<SystemTime>) -> ExtractorChooser<'extractor> {
+        ExtractorChooser {
+            modified_after: time,
+            ..self
+        }
+    }
+
+    pub fn choose(&self, entry: &DirEntry) -> Choice<'extractor> {
+        // we read the start of a file at most once, no matter how many
+        // things want to look at it.
+        let head = OnceCell::new();
+        let head = || {
+            head.get_or_init(|| detect::read_head(entry.path()).ok())
+                .as_deref()
+        };
+
+        let extractors = self.extractors_for(entry, head);
+        if extractors.is_empty() {
+            return Choice::Skip;
+        }
+
+        match self.skip_reason(entry, head) {
+            Some(reason) => Choice::Skipped(reason),
+            None => Choice::Search(extractors),
+        }
+    }
+
+    fn skip_reason<'head>(
+        &self,
+        entry: &DirEntry,
+        head: impl Fn() -> Option<&'head [u8]>,
+    ) -> Option<SkipReason> {
+        // if we can't get metadata, we can't read the file either. Trying to
+        // search it will report that.
+        if self.max_filesize.is_some() || self.modified_after.is_some() {
+            if let Ok(metadata) = entry.metadata() {
+                if let Some(max) = self.max_filesize {
+                    if metadata.len() > max {
+                        return Some(SkipReason::TooBig {
+                            size: metadata.len(),
+                            max,
+                        });
+                    }
+                }
+
+                if let (Some(after), Ok(modified)) = (self.modified_after, metadata.modified()) {
+                    if modified <= after {
+                        return Some(SkipReason::Unmodified);
+                    }
+                }
+            }
+        }
+
+        // depth 0 is a path that was given to us directly, instead of one we
+        // found in a directory. We trust that those are worth searching.
+        if entry.depth() == 0 {
             return None;
         }
 
-        matched
-            .inner()
-            .and_then(|glob| glob.file_type_def())
-            .and_then(|def| self.extractors.get(def.name()))
+        if self.skip_generated
+            && (generated::is_marked_in_attributes(&self.attributes, entry.path())
+                || head().is_some_and(generated::has_marker))
+        {
+            return Some(SkipReason::Generated);
+        }
+
+        if self.skip_binary && head().is_some_and(skip::is_binary) {
+            return Some(SkipReason::Binary);
+        }
+
+        if self.skip_minified && head().is_some_and(skip::is_minified) {
+            return Some(SkipReason::Minified);
+        }
+
+        None
+    }
+
+    fn extractors_for<'head>(
+        &self,
+        entry: &DirEntry,
+        head: impl Fn() -> Option<&'head [u8]>,
+    ) -> Vec<&'extractor Extractor> {
+        let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(true);
+        if is_dir {
+            return Vec::new();
+        }
+
+        if self.all_types.is_some() {
+            if let Some(Value::Text(name)) = self.attributes.get(entry.path(), "linguist-language")
+            {
+                if let Some(language) = Language::from_alias(&name) {
+                    return self.extractor_for_language(&language).into_iter().collect();
+                }
+            }
+        }
+
+        let mut matched: Vec<&'extractor Extractor> = self
+            .matchers
+            .iter()
+            .filter(|(matcher, _)| matcher.matched(entry.path(), false).is_whitelist())
+            .map(|(_, extractor)| *extractor)
+            .collect();
+
+        if !matched.is_empty() {
+            if let Some(header_language) = &self.header_language {
+                if entry.path().extension().and_then(|ext| ext.to_str()) == Some("h") {
+                    matched.retain(|extractor| extractor.language() == header_language);
+                }
+            }
+
+            return matched;
+        }
+
+        let all_types = match &self.all_types {
+            Some(all_types) => all_types,
+            None => return Vec::new(),
+        };
+        if all_types.matched(entry.path(), false).is_whitelist() {
+            return Vec::new();
+        }
+
+        // files we can't read are skipped here, like any other file we don't
+        // have a language for. Reading them for real would fail anyway.
+        head()
+            .and_then(detect::language_from_head)
+            .and_then(|language| self.extractor_for_language(&language))
+            .into_iter()
+            .collect()
+    }
+
+    fn extractor_for_language(&self, language: &Language) -> Option<&'extractor Extractor> {
+        self.extractors
+            .get(language.name_for_types_builder())
             .copied()
     }
 }
@@ -0,0 +1,65 @@
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+/// What we were doing when something went wrong with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// We couldn't read the file.
+    Read,
+    /// We read the file, but couldn't parse or search it.
+    Parse,
+    /// We couldn't turn text back into the file's encoding (for example,
+    /// when a rewrite puts `€` in a Latin-1 file.)
+    Encoding,
+    /// We couldn't walk a directory (or see what was in it) while looking
+    /// for files.
+    Walk,
+}
+
+/// The file an error is about, and what kind of error it is. The searcher
+/// attaches this as context to the errors it reports, so you can get it back
+/// with `error.downcast_ref::<FileError>()`. It's also the first part of the
+/// error's message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    kind: ErrorKind,
+    path: Option<PathBuf>,
+}
+
+impl FileError {
+    pub fn new(kind: ErrorKind, path: Option<&Path>) -> FileError {
+        FileError {
+            kind,
+            path: path.map(|path| path.to_owned()),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The path of the file (or directory) with the problem. This is `None`
+    /// for stdin without a label, and for walker errors that don't say.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = match &self.path {
+            Some(path) => path.display().to_string(),
+            None => String::from("stdin"),
+        };
+
+        match self.kind {
+            ErrorKind::Read => write!(f, "could not read {}", path),
+            ErrorKind::Parse => write!(f, "could not search {}", path),
+            ErrorKind::Encoding => write!(f, "could not encode {}", path),
+            // walker errors already include the path
+            ErrorKind::Walk => f.write_str("could not walk the filesystem"),
+        }
+    }
+}
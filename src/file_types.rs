@@ -0,0 +1,169 @@
+use crate::language::Language;
+use anyhow::{Context, Result};
+use ignore::types::TypesBuilder;
+
+/// Which files get parsed as which language. This starts from the `ignore`
+/// crate's file types (plus a few of our own) and can be changed by adding
+/// globs to a language, or by moving a glob from whatever language has it to
+/// another one.
+///
+/// A file can be matched by more than one language (`.h` files are C, C++,
+/// and CUDA), in which case it's searched with the queries for each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTypes {
+    additions: Vec<(Language, String)>,
+    remaps: Vec<(String, Language)>,
+    header_language: Option<Language>,
+}
+
+impl FileTypes {
+    pub fn new() -> FileTypes {
+        FileTypes::default()
+    }
+
+    /// Parse files matching `glob` as `language`, in addition to the files
+    /// it already has.
+    pub fn add(&mut self, language: Language, glob: impl Into<String>) -> &mut FileTypes {
+        self.additions.push((language, glob.into()));
+        self
+    }
+
+    /// Parse files matching `glob` as `language` instead of whatever language
+    /// they would have been parsed as. `glob` has to be exactly the same as
+    /// the one you're replacing (for example `*.jsx`, as shown by
+    /// `--type-list`.)
+    pub fn parse_as(&mut self, glob: impl Into<String>, language: Language) -> &mut FileTypes {
+        self.remaps.push((glob.into(), language));
+        self
+    }
+
+    /// Search `.h` files only as `language`, even if they match globs for
+    /// other languages you have queries for. `.h` files are matched by
+    /// patterns like `*.[chH]` instead of a glob of their own, so `parse_as`
+    /// can't do this.
+    pub fn parse_headers_as(&mut self, language: Language) -> &mut FileTypes {
+        self.header_language = Some(language);
+        self
+    }
+
+    /// The language `.h` files are searched as, if there's just one.
+    pub fn header_language(&self) -> Option<&Language> {
+        self.header_language.as_ref()
+    }
+
+    pub fn types_builder(&self) -> Result<TypesBuilder> {
+        let mut builder = TypesBuilder::new();
+        builder.add_defaults();
+        builder.add_def("cuda:include:cpp")?;
+        builder.add_def("powershell:*.ps1")?;
+        // notebooks are mostly Python. The searcher checks what their kernel
+        // actually is.
+        builder.add("py", "*.ipynb")?;
+
+        // `.tsx` files need the TSX grammar to parse JSX, so they move over
+        // from TypeScript. The other dialects that share a repository with
+        // another grammar (markdown_inline and php_only) don't have
+        // extensions of their own, so they don't get any globs unless you add
+        // them with `--type-add`.
+        if let Some(ts) = builder
+            .definitions()
+            .into_iter()
+            .find(|def| def.name() == "ts")
+        {
+            builder.clear("ts");
+            for glob in ts.globs().iter().filter(|glob| *glob != "*.tsx") {
+                builder.add("ts", glob)?;
+            }
+        }
+        builder.add("tsx", "*.tsx")?;
+
+        for (language, glob) in &self.additions {
+            builder
+                .add(language.name_for_types_builder(), glob)
+                .with_context(|| format!("could not add {} to {}", glob, language))?;
+        }
+
+        for (glob, language) in &self.remaps {
+            for def in builder.definitions() {
+                if def.globs().contains(glob) {
+                    builder.clear(def.name());
+                    for other in def.globs().iter().filter(|other| *other != glob) {
+                        builder.add(def.name(), other)?;
+                    }
+                }
+            }
+
+            builder
+                .add(language.name_for_types_builder(), glob)
+                .with_context(|| format!("could not parse {} as {}", glob, language))?;
+        }
+
+        Ok(builder)
+    }
+
+    /// The globs for each language, sorted.
+    pub fn globs(&self) -> Result<Vec<(Language, Vec<String>)>> {
+        let definitions = self.types_builder()?.definitions();
+
+        Ok(Language::all()
+            .into_iter()
+            .map(|language| {
+                let globs = definitions
+                    .iter()
+                    .find(|def| def.name() == language.name_for_types_builder())
+                    .map(|def| def.globs().to_vec())
+                    .unwrap_or_default();
+
+                (language, globs)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(
+    not(any(feature = "javascript", all(feature = "tsx", feature = "typescript"))),
+    allow(dead_code)
+)]
+mod tests {
+    use super::*;
+
+    fn globs_for(file_types: &FileTypes, language: Language) -> Vec<String> {
+        file_types
+            .globs()
+            .unwrap()
+            .into_iter()
+            .find(|(lang, _)| *lang == language)
+            .map(|(_, globs)| globs)
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn globs_can_be_added() {
+        let mut file_types = FileTypes::new();
+        file_types.add(Language::JavaScript, "Jakefile");
+
+        assert!(globs_for(&file_types, Language::JavaScript).contains(&String::from("Jakefile")));
+    }
+
+    #[test]
+    #[cfg(all(feature = "javascript", feature = "typescript"))]
+    fn globs_can_be_moved_to_another_language() {
+        let mut file_types = FileTypes::new();
+        file_types.parse_as("*.jsx", Language::TypeScript);
+
+        assert!(!globs_for(&file_types, Language::JavaScript).contains(&String::from("*.jsx")));
+        assert!(globs_for(&file_types, Language::JavaScript).contains(&String::from("*.js")));
+        assert!(globs_for(&file_types, Language::TypeScript).contains(&String::from("*.jsx")));
+    }
+
+    #[test]
+    #[cfg(all(feature = "tsx", feature = "typescript"))]
+    fn tsx_files_are_not_typescript() {
+        let file_types = FileTypes::new();
+
+        assert!(globs_for(&file_types, Language::Tsx).contains(&String::from("*.tsx")));
+        assert!(!globs_for(&file_types, Language::TypeScript).contains(&String::from("*.tsx")));
+    }
+}
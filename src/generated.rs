@@ -0,0 +1,49 @@
+use crate::gitattributes::{GitAttributes, Value};
+use std::path::Path;
+
+/// How many lines at the top of a file we look at for a marker.
+const MARKER_LINES: usize = 5;
+
+/// Whether `.gitattributes` says the file at `path` is generated
+/// (`linguist-generated`) or vendored (`linguist-vendored`). These are the
+/// same attributes GitHub uses to leave files out of diffs and language
+/// stats.
+pub fn is_marked_in_attributes(attributes: &GitAttributes, path: &Path) -> bool {
+    ["linguist-generated", "linguist-vendored"]
+        .iter()
+        .any(|attribute| match attributes.get(path, attribute) {
+            Some(Value::Set) => true,
+            Some(Value::Text(value)) => value != "false",
+            Some(Value::Unset) | None => false,
+        })
+}
+
+/// Whether the start of a file says it was generated: `@generated` (which a
+/// lot of code generators write) or `DO NOT EDIT` (like Go's `// Code
+/// generated by ... DO NOT EDIT.` or protoc's headers) in the first few lines.
+pub fn has_marker(head: &[u8]) -> bool {
+    head.split(|byte| *byte == b'\n')
+        .take(MARKER_LINES)
+        .any(|line| contains(line, b"@generated") || contains(line, b"DO NOT EDIT"))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_are_in_the_header() {
+        assert!(has_marker(
+            b"// Code generated by protoc-gen-go. DO NOT EDIT.\npackage foo\n"
+        ));
+        assert!(has_marker(b"/**\n * @generated SignedSource<<abc>>\n */\n"));
+        assert!(!has_marker(b"package foo\n"));
+        assert!(!has_marker(b"1\n2\n3\n4\n5\n// @generated\n"));
+    }
+}
@@ -0,0 +1,200 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What a `.gitattributes` file says about an attribute: `attr` sets it,
+/// `-attr` unsets it, and `attr=value` gives it a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Set,
+    Unset,
+    Text(String),
+}
+
+/// Looks up attributes in the `.gitattributes` files above a path, up to the
+/// root of the repository it's in. Like git, deeper files win over shallower
+/// ones and later lines win over earlier ones. We don't read
+/// `.git/info/attributes` or the global attributes file.
+///
+/// Files are read the first time something in their directory is looked up,
+/// and kept around after that.
+#[derive(Debug, Default)]
+pub struct GitAttributes {
+    dirs: Mutex<HashMap<PathBuf, Arc<Dir>>>,
+}
+
+#[derive(Debug)]
+struct Dir {
+    lines: Vec<Line>,
+    is_repo_root: bool,
+}
+
+#[derive(Debug)]
+struct Line {
+    pattern: Gitignore,
+    // `None` is `!attr`, which puts the attribute back the way it was before
+    // any file mentioned it.
+    attributes: Vec<(String, Option<Value>)>,
+}
+
+impl GitAttributes {
+    pub fn new() -> GitAttributes {
+        GitAttributes::default()
+    }
+
+    /// The value of `attribute` for the file at `path`, or `None` if no
+    /// `.gitattributes` file says anything about it.
+    pub fn get(&self, path: &Path, attribute: &str) -> Option<Value> {
+        let path = std::path::absolute(path).ok()?;
+        let mut current = path.parent();
+
+        while let Some(dir_path) = current {
+            let dir = self.dir(dir_path);
+            if let Some(value) = dir.lookup(&path, attribute) {
+                return value;
+            }
+
+            if dir.is_repo_root {
+                break;
+            }
+            current = dir_path.parent();
+        }
+
+        None
+    }
+
+    fn dir(&self, path: &Path) -> Arc<Dir> {
+        // a poisoned cache only means another thread panicked while reading
+        // a file, and everything in it is still fine to use.
+        let mut dirs = match self.dirs.lock() {
+            Ok(dirs) => dirs,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        dirs.entry(path.to_owned())
+            .or_insert_with(|| Arc::new(Dir::read(path)))
+            .clone()
+    }
+}
+
+impl Dir {
+    /// Read the `.gitattributes` in `path`. A file we can't read counts as
+    /// empty, as do lines we don't understand, so that a broken attributes
+    /// file doesn't stop a search.
+    fn read(path: &Path) -> Dir {
+        let lines = fs::read_to_string(path.join(".gitattributes"))
+            .map(|source| parse(path, &source))
+            .unwrap_or_default();
+
+        Dir {
+            lines,
+            is_repo_root: path.join(".git").exists(),
+        }
+    }
+
+    /// What the last line that matches `path` and mentions `attribute` says
+    /// about it. The outer `None` means no line did.
+    fn lookup(&self, path: &Path, attribute: &str) -> Option<Option<Value>> {
+        self.lines.iter().rev().find_map(|line| {
+            let (_, value) = line
+                .attributes
+                .iter()
+                .rev()
+                .find(|(name, _)| name == attribute)?;
+
+            if line.pattern.matched(path, false).is_ignore() {
+                Some(value.clone())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+fn parse(dir: &Path, source: &str) -> Vec<Line> {
+    source
+        .lines()
+        .filter_map(|line| parse_line(dir, line))
+        .collect()
+}
+
+fn parse_line(dir: &Path, line: &str) -> Option<Line> {
+    let mut words = line.split_whitespace();
+    let pattern = words.next()?;
+
+    // comments, macro definitions (`[attr]name ...`), and negative patterns
+    // (which git doesn't allow in attributes files)
+    if pattern.starts_with('#') || pattern.starts_with('[') || pattern.starts_with('!') {
+        return None;
+    }
+
+    let mut builder = GitignoreBuilder::new(dir);
+    builder.add_line(None, pattern).ok()?;
+    let pattern = builder.build().ok()?;
+
+    let attributes = words
+        .map(|word| {
+            if let Some(name) = word.strip_prefix('-') {
+                (name.to_string(), Some(Value::Unset))
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name.to_string(), None)
+            } else if let Some((name, value)) = word.split_once('=') {
+                (name.to_string(), Some(Value::Text(value.to_string())))
+            } else {
+                (word.to_string(), Some(Value::Set))
+            }
+        })
+        .collect();
+
+    Some(Line {
+        pattern,
+        attributes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(source: &str, path: &str, attribute: &str) -> Option<Value> {
+        let dir = Dir {
+            lines: parse(Path::new("/repo"), source),
+            is_repo_root: true,
+        };
+
+        dir.lookup(Path::new(path), attribute).flatten()
+    }
+
+    #[test]
+    fn attributes_have_values() {
+        let source = "# a comment\n*.h linguist-language=C++ -diff\nbin/* text !eol\n";
+
+        assert_eq!(
+            lookup(source, "/repo/src/foo.h", "linguist-language"),
+            Some(Value::Text(String::from("C++")))
+        );
+        assert_eq!(
+            lookup(source, "/repo/src/foo.h", "diff"),
+            Some(Value::Unset)
+        );
+        assert_eq!(lookup(source, "/repo/bin/deploy", "text"), Some(Value::Set));
+        assert_eq!(lookup(source, "/repo/bin/deploy", "eol"), None);
+        assert_eq!(lookup(source, "/repo/src/bin/deploy", "text"), None);
+    }
+
+    #[test]
+    fn later_lines_win() {
+        let source = "* linguist-language=Ruby\nscripts/* linguist-language=Python\n";
+
+        assert_eq!(
+            lookup(source, "/repo/scripts/build", "linguist-language"),
+            Some(Value::Text(String::from("Python")))
+        );
+        assert_eq!(
+            lookup(source, "/repo/build", "linguist-language"),
+            Some(Value::Text(String::from("Ruby")))
+        );
+    }
+}
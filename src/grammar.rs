@@ -0,0 +1,418 @@
+use crate::grammar_compiler;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tree_sitter::Parser;
+
+// Grammars are looked up by name wherever we parse a `Language` from a
+// string, so they have to live somewhere everyone can see them. They're
+// never unloaded, so the languages we hand out stay valid.
+static GRAMMARS: RwLock<Vec<Grammar>> = RwLock::new(Vec::new());
+
+/// A tree-sitter grammar loaded from a shared library at runtime, instead of
+/// being compiled in.
+#[derive(Clone)]
+pub struct Grammar {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    type_name: String,
+    path: PathBuf,
+    language: tree_sitter::Language,
+    // native languages point into this, so we need to keep it around. Wasm
+    // languages don't have one.
+    _library: Option<libloading::Library>,
+}
+
+impl Grammar {
+    /// Load the grammar called `name` from the shared library at `path`.
+    /// The library needs to export a `tree_sitter_NAME` function, like the
+    /// ones built by `tree-sitter build`. Dashes in `name` become underscores
+    /// in the function name.
+    ///
+    /// If `path` ends in `.wasm`, it's loaded as a WebAssembly grammar (like
+    /// the ones built by `tree-sitter build --wasm`.) That needs the `wasm`
+    /// feature.
+    ///
+    /// If `path` is a directory, we treat it as a checkout of the grammar's
+    /// repository and compile it (or reuse the library from last time, if
+    /// nothing changed.)
+    pub fn load(name: &str, path: &Path) -> Result<Grammar> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!(
+                "`{}` isn't a valid grammar name. Use only letters, numbers, `-`, and `_`.",
+                name
+            )
+        }
+
+        let (library, language) = if path.is_dir() {
+            let cache_dir = grammar_compiler::default_cache_dir()
+                .context("could not find a cache directory to compile grammars in. Is HOME set?")?;
+
+            let library_path = grammar_compiler::compile(name, path, &cache_dir)?;
+            let (library, language) = load_native(name, &library_path)?;
+            (Some(library), language)
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("wasm") {
+            (None, load_wasm(name, path)?)
+        } else {
+            let (library, language) = load_native(name, path)?;
+            (Some(library), language)
+        };
+
+        let version = language.abi_version();
+        if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+            .contains(&version)
+        {
+            bail!(
+                "the grammar in {} uses version {} of the tree-sitter ABI, but I can only use versions {} through {}. Try regenerating it with a matching version of the tree-sitter CLI.",
+                path.display(),
+                version,
+                tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+                tree_sitter::LANGUAGE_VERSION,
+            )
+        }
+
+        Ok(Grammar {
+            inner: Arc::new(Inner {
+                name: name.to_string(),
+                type_name: name.chars().filter(|c| c.is_ascii_alphanumeric()).collect(),
+                path: path.to_owned(),
+                language,
+                _library: library,
+            }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// The name of the file type for this grammar. This is the grammar name
+    /// with everything but letters and numbers taken out, which means that
+    /// grammars for languages the `ignore` crate already knows about (like
+    /// `toml` or `lua`) get their globs for free.
+    pub fn type_name(&self) -> &str {
+        &self.inner.type_name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    pub fn language(&self) -> tree_sitter::Language {
+        self.inner.language.clone()
+    }
+}
+
+impl PartialEq for Grammar {
+    fn eq(&self, other: &Grammar) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for Grammar {}
+
+impl Hash for Grammar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name().hash(state)
+    }
+}
+
+impl Debug for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Grammar")
+            .field("name", &self.name())
+            .field("path", &self.path())
+            .finish()
+    }
+}
+
+impl Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn load_native(name: &str, path: &Path) -> Result<(libloading::Library, tree_sitter::Language)> {
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+
+    // Safety: loading a library runs its initialization code, and we have to
+    // trust that the function we find has the right signature. Both are true
+    // of grammars built with the tree-sitter CLI, and there isn't a way to
+    // check any of it without running it.
+    unsafe {
+        let library = libloading::Library::new(path)
+            .with_context(|| format!("could not load a grammar from {}", path.display()))?;
+
+        let constructor = library
+            .get::<unsafe extern "C" fn() -> tree_sitter::Language>(symbol.as_bytes())
+            .with_context(|| {
+                format!(
+                    "could not find {} in {}. Is the grammar name right?",
+                    symbol,
+                    path.display()
+                )
+            })?;
+        let language = constructor();
+
+        Ok((library, language))
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn wasm_engine() -> &'static tree_sitter::wasmtime::Engine {
+    static ENGINE: std::sync::OnceLock<tree_sitter::wasmtime::Engine> = std::sync::OnceLock::new();
+    ENGINE.get_or_init(tree_sitter::wasmtime::Engine::default)
+}
+
+#[cfg(feature = "wasm")]
+fn load_wasm(name: &str, path: &Path) -> Result<tree_sitter::Language> {
+    let bytes = fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+
+    let mut store = tree_sitter::WasmStore::new(wasm_engine())
+        .map_err(|err| anyhow!("could not set up a wasm store: {}", err.message))?;
+
+    store
+        .load_language(&name.replace('-', "_"), &bytes)
+        .map_err(|err| {
+            anyhow!(
+                "could not load a grammar from {}: {}",
+                path.display(),
+                err.message
+            )
+        })
+}
+
+#[cfg(not(feature = "wasm"))]
+fn load_wasm(_name: &str, path: &Path) -> Result<tree_sitter::Language> {
+    bail!(
+        "{} is a WebAssembly grammar, but this tree-grepper was built without the `wasm` feature",
+        path.display()
+    )
+}
+
+/// Get `parser` ready to parse `language`. This is just `set_language` for
+/// native grammars, but wasm grammars also need the parser to have a wasm
+/// store, so we give it one the first time it needs it.
+pub fn set_parser_language(parser: &mut Parser, language: &tree_sitter::Language) -> Result<()> {
+    #[cfg(feature = "wasm")]
+    if language.is_wasm() && parser.set_language(language).is_err() {
+        let store = tree_sitter::WasmStore::new(wasm_engine())
+            .map_err(|err| anyhow!("could not set up a wasm store: {}", err.message))?;
+        parser
+            .set_wasm_store(store)
+            .context("could not give the parser a wasm store")?;
+    }
+
+    parser
+        .set_language(language)
+        .context("could not set language")
+}
+
+/// Make a grammar available everywhere languages are looked up by name, like
+/// `Language::from_str` and `Language::all`. Names have to be unique, and
+/// can't be the same as a built-in language.
+pub fn register(grammar: Grammar) -> Result<()> {
+    if crate::language::Language::iter_builtin().any(|builtin| {
+        builtin.to_string() == grammar.name()
+            || builtin.name_for_types_builder() == grammar.type_name()
+    }) {
+        bail!(
+            "can't load a grammar named {} because there's already a built-in language with that name",
+            grammar.name()
+        )
+    }
+
+    let mut grammars = GRAMMARS.write().map_err(|_| {
+        anyhow!(
+            "the grammar registry was poisoned. This is an internal error and should be reported."
+        )
+    })?;
+
+    if let Some(existing) = grammars
+        .iter()
+        .find(|existing| existing.type_name() == grammar.type_name())
+    {
+        bail!(
+            "can't load the {} grammar from {} because {} was already loaded from {}",
+            grammar.name(),
+            grammar.path().display(),
+            existing.name(),
+            existing.path().display(),
+        )
+    }
+
+    grammars.push(grammar);
+    Ok(())
+}
+
+/// Every grammar that has been registered, sorted by name.
+pub fn registered() -> Vec<Grammar> {
+    let mut grammars = match GRAMMARS.read() {
+        Ok(grammars) => grammars.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+
+    grammars.sort_by(|a, b| a.name().cmp(b.name()));
+    grammars
+}
+
+/// Load and register every grammar in `dir`. Files are expected to be named
+/// like `libtree-sitter-NAME.so` (or `.dylib`, or `tree-sitter-NAME.dll`, or
+/// `tree-sitter-NAME.wasm`), and directories like `tree-sitter-NAME` are
+/// compiled. Anything else is skipped. It's fine if `dir` doesn't exist.
+///
+/// This happens on every run, so one broken grammar shouldn't stop people
+/// from searching with all the others. Instead of failing, we skip anything
+/// we can't load (or a directory we can't read) and return the problems so
+/// the caller can warn about them.
+pub fn register_dir(dir: &Path) -> Vec<anyhow::Error> {
+    let mut problems = Vec::new();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return problems,
+        Err(err) => {
+            problems.push(
+                anyhow::Error::new(err)
+                    .context(format!("could not read grammars from {}", dir.display())),
+            );
+            return problems;
+        }
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(err) => problems.push(
+                anyhow::Error::new(err)
+                    .context(format!("could not read an entry from {}", dir.display())),
+            ),
+        }
+    }
+    paths.sort();
+
+    for path in paths {
+        if let Some(name) = grammar_name_from_path(&path) {
+            if let Err(problem) = Grammar::load(&name, &path).and_then(register) {
+                problems.push(problem.context(format!("skipped {}", path.display())));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Where we look for grammars if nobody tells us otherwise:
+/// `$XDG_CONFIG_HOME/tree-grepper/grammars`, or
+/// `~/.config/tree-grepper/grammars` if that isn't set.
+pub fn default_dir() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => PathBuf::from(config),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config.join("tree-grepper").join("grammars"))
+}
+
+fn grammar_name_from_path(path: &Path) -> Option<String> {
+    if path.is_dir() {
+        return path
+            .file_name()?
+            .to_str()?
+            .strip_prefix("tree-sitter-")
+            .map(|name| name.to_string());
+    }
+
+    match path.extension()?.to_str()? {
+        "so" | "dylib" | "dll" | "wasm" => (),
+        _ => return None,
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    let stem = stem.strip_prefix("lib").unwrap_or(stem);
+
+    stem.strip_prefix("tree-sitter-")
+        .map(|name| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_come_from_library_file_names() {
+        assert_eq!(
+            grammar_name_from_path(Path::new("grammars/libtree-sitter-foo-bar.so")),
+            Some(String::from("foo-bar"))
+        );
+        assert_eq!(
+            grammar_name_from_path(Path::new("grammars/tree-sitter-foo.dll")),
+            Some(String::from("foo"))
+        );
+        assert_eq!(
+            grammar_name_from_path(Path::new("grammars/tree-sitter-foo.wasm")),
+            Some(String::from("foo"))
+        );
+        assert_eq!(
+            grammar_name_from_path(Path::new("grammars/README.md")),
+            None
+        );
+        assert_eq!(
+            grammar_name_from_path(Path::new("grammars/libfoo.so")),
+            None
+        );
+    }
+
+    #[test]
+    fn loading_needs_a_library() {
+        assert!(Grammar::load("foo", Path::new("does-not-exist.so")).is_err());
+        assert!(Grammar::load("foo bar", Path::new("does-not-exist.so")).is_err());
+    }
+
+    #[test]
+    fn broken_grammars_in_a_dir_are_skipped() {
+        let problems = register_dir(Path::new("tests/grammars"));
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0]
+            .to_string()
+            .starts_with("skipped tests/grammars/libtree-sitter-broken.dylib"));
+        assert!(!registered()
+            .iter()
+            .any(|grammar| grammar.name() == "broken"));
+    }
+
+    #[test]
+    fn unreadable_grammar_dirs_are_problems_too() {
+        let problems = register_dir(Path::new("tests/grammars/libtree-sitter-broken.dylib"));
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].to_string(),
+            "could not read grammars from tests/grammars/libtree-sitter-broken.dylib"
+        );
+        assert!(register_dir(Path::new("tests/grammars/missing")).is_empty());
+    }
+
+    #[test]
+    #[cfg(not(feature = "wasm"))]
+    fn loading_wasm_needs_the_feature() {
+        assert_eq!(
+            Grammar::load("foo", Path::new("tree-sitter-foo.wasm"))
+                .unwrap_err()
+                .to_string(),
+            "tree-sitter-foo.wasm is a WebAssembly grammar, but this tree-grepper was built without the `wasm` feature"
+        );
+    }
+}
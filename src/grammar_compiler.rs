@@ -0,0 +1,282 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// set by build.rs, so we compile grammars for the same target we were
+// compiled for.
+const TARGET: &str = env!("TREE_GREPPER_TARGET");
+
+/// Compile the grammar in a checkout (a directory with `src/parser.c` in it,
+/// or the `src` directory itself) to a shared library, and return the path
+/// to the library. Libraries are kept in `cache_dir` and only rebuilt when a
+/// source file is newer than the library.
+pub fn compile(name: &str, checkout: &Path, cache_dir: &Path) -> Result<PathBuf> {
+    let src_dir = if checkout.join("src").join("parser.c").is_file() {
+        checkout.join("src")
+    } else if checkout.join("parser.c").is_file() {
+        checkout.to_owned()
+    } else {
+        bail!(
+            "{} doesn't look like a grammar. I expected to find src/parser.c in it.",
+            checkout.display()
+        )
+    };
+
+    let src_dir = src_dir
+        .canonicalize()
+        .with_context(|| format!("could not find the absolute path of {}", src_dir.display()))?;
+
+    let sources = sources(&src_dir)?;
+    let library = cache_dir.join(library_name(name, &src_dir, &sources));
+
+    if is_fresh(&library, &sources) {
+        return Ok(library);
+    }
+
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("could not create {}", cache_dir.display()))?;
+
+    // we build to a temporary file first so that a failed or interrupted
+    // build never leaves a broken library where we'd find it next time.
+    let temporary = library.with_extension(format!("{}.tmp", std::process::id()));
+    build(&src_dir, &temporary)?;
+    fs::rename(&temporary, &library).with_context(|| {
+        format!(
+            "could not move the compiled grammar to {}",
+            library.display()
+        )
+    })?;
+
+    Ok(library)
+}
+
+/// Where compiled grammars go: `$XDG_CACHE_HOME/tree-grepper/grammars`, or
+/// `~/.cache/tree-grepper/grammars` if that isn't set.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) if !cache.is_empty() => PathBuf::from(cache),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+
+    Some(cache.join("tree-grepper").join("grammars"))
+}
+
+fn build(src_dir: &Path, out: &Path) -> Result<()> {
+    let parser = src_dir.join("parser.c");
+    let c_scanner = src_dir.join("scanner.c");
+    let cpp_scanner = src_dir.join("scanner.cc");
+
+    // this is the same setup build.rs uses for the grammars we vendor, plus
+    // what `cc` would usually get from cargo's environment variables.
+    let mut config = cc::Build::new();
+    config
+        .cargo_metadata(false)
+        .cargo_warnings(false)
+        .target(TARGET)
+        .host(TARGET)
+        .opt_level(2)
+        .debug(false)
+        .pic(true)
+        .include(src_dir)
+        .warnings(false)
+        .cpp(cpp_scanner.is_file());
+
+    let compiler = config
+        .try_get_compiler()
+        .context("could not find a C compiler to build the grammar with")?;
+    let mut command = compiler.to_command();
+
+    if compiler.is_like_msvc() {
+        command.arg("/LD").arg(&parser);
+        if c_scanner.is_file() {
+            command.arg(&c_scanner);
+        } else if cpp_scanner.is_file() {
+            command.arg(&cpp_scanner);
+        }
+        command.arg("/link").arg(format!("/out:{}", out.display()));
+    } else {
+        command.arg("-shared").arg("-o").arg(out);
+        command.arg("-xc").arg(&parser);
+        if c_scanner.is_file() {
+            command.arg(&c_scanner);
+        } else if cpp_scanner.is_file() {
+            command.arg("-xc++").arg(&cpp_scanner);
+        }
+    }
+
+    let output = command
+        .output()
+        .with_context(|| format!("could not run the compiler for {}", src_dir.display()))?;
+
+    if !output.status.success() {
+        bail!(
+            "could not compile the grammar in {}:\n{}",
+            src_dir.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+
+    Ok(())
+}
+
+/// Every file that goes into the library: the parser, the scanner, and any
+/// headers next to them or in `tree_sitter/`, sorted.
+fn sources(src_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+
+    for dir in [src_dir.to_owned(), src_dir.join("tree_sitter")] {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read {}", dir.display()))
+            }
+        };
+
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("could not read an entry from {}", dir.display()))?
+                .path();
+
+            if matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("c" | "cc" | "h")
+            ) {
+                out.push(path);
+            }
+        }
+    }
+
+    out.sort();
+    Ok(out)
+}
+
+fn is_fresh(library: &Path, sources: &[PathBuf]) -> bool {
+    let built = match fs::metadata(library).and_then(|metadata| metadata.modified()) {
+        Ok(built) => built,
+        Err(_) => return false,
+    };
+
+    for source in sources {
+        let modified = fs::metadata(source)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::now());
+
+        if modified > built {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Libraries are named after the grammar and where its source lives, so that
+/// two checkouts of the same grammar don't overwrite each other. The names of
+/// the source files go in too, since `is_fresh` can only see files that are
+/// still there: if one gets deleted, we need a new library.
+///
+/// The cache outlives any one build of tree-grepper, so this has to hash the
+/// same way every time. `DefaultHasher` doesn't promise that, so we use FNV.
+fn library_name(name: &str, src_dir: &Path, sources: &[PathBuf]) -> String {
+    let mut hash = Fnv::new();
+    hash.write(src_dir.as_os_str().as_encoded_bytes());
+    for source in sources {
+        let relative = source.strip_prefix(src_dir).unwrap_or(source);
+        hash.write(&[0]);
+        hash.write(relative.as_os_str().as_encoded_bytes());
+    }
+
+    let extension = if cfg!(target_os = "windows") {
+        "dll"
+    } else if cfg!(target_os = "macos") {
+        "dylib"
+    } else {
+        "so"
+    };
+
+    format!(
+        "libtree-sitter-{}-{:016x}.{}",
+        name,
+        hash.finish(),
+        extension
+    )
+}
+
+/// 64-bit FNV-1a. See http://www.isthe.com/chongo/tech/comp/fnv/
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn libraries_depend_on_the_source_location() {
+        assert_ne!(
+            library_name("foo", Path::new("/one/src"), &[]),
+            library_name("foo", Path::new("/two/src"), &[])
+        );
+        assert!(library_name("foo", Path::new("/one/src"), &[]).starts_with("libtree-sitter-foo-"));
+    }
+
+    #[test]
+    fn libraries_depend_on_which_sources_there_are() {
+        let src_dir = Path::new("/one/src");
+        let with_scanner = [src_dir.join("parser.c"), src_dir.join("scanner.c")];
+
+        assert_ne!(
+            library_name("foo", src_dir, &with_scanner),
+            library_name("foo", src_dir, &with_scanner[..1])
+        );
+    }
+
+    #[test]
+    fn library_names_are_stable() {
+        // if this changes, everyone's cached grammars get rebuilt.
+        assert_eq!(Fnv::new().finish(), 0xcbf2_9ce4_8422_2325);
+        let mut hash = Fnv::new();
+        hash.write(b"a");
+        assert_eq!(hash.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn missing_libraries_are_not_fresh() {
+        assert!(!is_fresh(Path::new("does-not-exist.so"), &[]));
+    }
+
+    #[test]
+    #[cfg(feature = "go")]
+    fn vendored_grammars_compile_once() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("tree-grepper-compile-{}", std::process::id()));
+
+        let library = compile("go", Path::new("vendor/tree-sitter-go"), &cache_dir).unwrap();
+        assert!(library.is_file());
+        let built = fs::metadata(&library).unwrap().modified().unwrap();
+
+        assert_eq!(
+            compile("go", Path::new("vendor/tree-sitter-go"), &cache_dir).unwrap(),
+            library
+        );
+        assert_eq!(fs::metadata(&library).unwrap().modified().unwrap(), built);
+
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
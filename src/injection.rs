@@ -0,0 +1,407 @@
+use crate::language::Language;
+use tree_sitter::{Node, Point, Range, Tree};
+
+/// Part of a file that's written in a different language than the rest of
+/// it, like a code block in Markdown. The ranges point into the host file, so
+/// anything parsed from them has positions in the host file too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Injection {
+    language: Language,
+    ranges: Vec<Range>,
+}
+
+impl Injection {
+    pub fn language(&self) -> &Language {
+        &self.language
+    }
+
+    /// The parts of the host file to parse. These are parsed together, as if
+    /// they were one document.
+    pub fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+}
+
+/// Find code in other languages in a file we've parsed as `language`. Right
+/// now that means:
+///
+/// - fenced code blocks in Markdown, using the language in the info string
+/// - doctests (`>>>` and `...` lines) in Python strings
+/// - code blocks in Rust doc comments, which are Rust unless they say
+///   otherwise
+pub fn find(language: &Language, tree: &Tree, source: &[u8]) -> Vec<Injection> {
+    let mut out = Vec::new();
+
+    match language.to_string().as_str() {
+        "markdown" => visit(tree.root_node(), "fenced_code_block", &mut |node| {
+            out.extend(markdown_fence(node, source))
+        }),
+        "python" => visit(tree.root_node(), "string_content", &mut |node| {
+            out.extend(python_doctest(language, node, source))
+        }),
+        "rust" => {
+            let mut comments = Vec::new();
+            visit(tree.root_node(), "line_comment", &mut |node| {
+                comments.push(node)
+            });
+            out.extend(rust_doc_blocks(language, &comments, source));
+        }
+        _ => (),
+    }
+
+    out
+}
+
+/// Call `f` with every node of the given kind, in document order.
+fn visit<'tree>(node: Node<'tree>, kind: &str, f: &mut impl FnMut(Node<'tree>)) {
+    if node.kind() == kind {
+        f(node);
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        visit(child, kind, f);
+    }
+}
+
+fn markdown_fence(node: Node, source: &[u8]) -> Option<Injection> {
+    let mut cursor = node.walk();
+    let mut tag = None;
+    let mut content = None;
+
+    for child in node.children(&mut cursor) {
+        match child.kind() {
+            "info_string" => tag = child.utf8_text(source).ok(),
+            "code_fence_content" => content = Some(child),
+            _ => (),
+        }
+    }
+
+    // info strings can have more than the language in them, like
+    // `rust,ignore` or `python {.numberLines}`
+    let tag = tag?
+        .split(|c: char| c == ',' || c == '{' || c.is_whitespace())
+        .next()?;
+    let content = content?;
+
+    Some(Injection {
+        language: Language::from_alias(tag)?,
+        ranges: vec![content.range()],
+    })
+}
+
+fn python_doctest(python: &Language, node: Node, source: &[u8]) -> Option<Injection> {
+    let mut ranges = Vec::new();
+    let mut in_example = false;
+
+    for line in lines(node, source) {
+        let text = &source[line.text_start..line.end];
+        let indent = text
+            .iter()
+            .take_while(|byte| **byte == b' ' || **byte == b'\t')
+            .count();
+        let text = &text[indent..];
+
+        let prompt = if text.starts_with(b">>>") {
+            in_example = true;
+            3
+        } else if in_example && text.starts_with(b"...") {
+            3
+        } else {
+            in_example = false;
+            continue;
+        };
+
+        // the prompt is followed by a space unless the line is empty
+        let prompt = match text.get(prompt) {
+            Some(b' ') => prompt + 1,
+            None => prompt,
+            Some(_) => {
+                in_example = false;
+                continue;
+            }
+        };
+
+        ranges.push(line.range_from(line.text_start + indent + prompt));
+    }
+
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(Injection {
+            language: python.clone(),
+            ranges,
+        })
+    }
+}
+
+/// Where we are in a doc comment: outside any code block, in a Rust block
+/// (with the ranges we've found so far), or in a block in some other language.
+enum Fence {
+    Outside,
+    Rust(Vec<Range>),
+    Other,
+}
+
+fn rust_doc_blocks(rust: &Language, comments: &[Node], source: &[u8]) -> Vec<Injection> {
+    let mut out = Vec::new();
+    let mut fence = Fence::Outside;
+    let mut last_row = None;
+
+    for comment in comments {
+        let start = comment.start_byte();
+        let marker = match source.get(start..start + 3) {
+            Some(b"///") if source.get(start + 3) != Some(&b'/') => 3,
+            Some(b"//!") => 3,
+            _ => continue,
+        };
+
+        // a doc comment ends at the first line that isn't part of it, and
+        // rustdoc closes any block that's still open when that happens.
+        let row = comment.start_position().row;
+        if last_row.map(|last| last + 1) != Some(row) {
+            finish_rust_block(
+                rust,
+                std::mem::replace(&mut fence, Fence::Outside),
+                &mut out,
+            );
+        }
+        last_row = Some(row);
+
+        let line = Line::containing(comment, source);
+        let mut from = start + marker;
+        if source.get(from) == Some(&b' ') {
+            from += 1;
+        }
+        let text = &source[from..line.end];
+        let trimmed = text.trim_ascii_start();
+
+        if trimmed.starts_with(b"```") || trimmed.starts_with(b"~~~") {
+            fence = match fence {
+                Fence::Outside if is_rust_info(&String::from_utf8_lossy(&trimmed[3..])) => {
+                    Fence::Rust(Vec::new())
+                }
+                Fence::Outside => Fence::Other,
+                closing => {
+                    finish_rust_block(rust, closing, &mut out);
+                    Fence::Outside
+                }
+            };
+        } else if let Fence::Rust(ranges) = &mut fence {
+            // rustdoc hides lines starting with `#`, but they're still code.
+            // `##` is how you write a line that really starts with `#`.
+            let indent = text.len() - trimmed.len();
+            let from = if trimmed.starts_with(b"##") || trimmed == b"#" {
+                from + indent + 1
+            } else if trimmed.starts_with(b"# ") {
+                from + indent + 2
+            } else {
+                from
+            };
+
+            ranges.push(line.range_from(from));
+        }
+    }
+
+    finish_rust_block(rust, fence, &mut out);
+    out
+}
+
+fn finish_rust_block(rust: &Language, fence: Fence, out: &mut Vec<Injection>) {
+    if let Fence::Rust(ranges) = fence {
+        if !ranges.is_empty() {
+            out.push(Injection {
+                language: rust.clone(),
+                ranges,
+            })
+        }
+    }
+}
+
+/// Whether a code block in a doc comment is Rust. rustdoc assumes it is
+/// unless the info string says otherwise, and the info string can also have
+/// attributes like `ignore` or `no_run` in it.
+fn is_rust_info(info: &str) -> bool {
+    info.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .all(|tag| {
+            matches!(
+                tag,
+                "rust"
+                    | "ignore"
+                    | "should_panic"
+                    | "no_run"
+                    | "compile_fail"
+                    | "test_harness"
+                    | "standalone_crate"
+            ) || tag.starts_with("edition")
+                || tag.starts_with("ignore-")
+        })
+}
+
+/// A line of source, not counting the newline at the end. `text_start` is
+/// where the part we care about starts, which can be after `start` if a node
+/// starts partway through the line.
+struct Line {
+    row: usize,
+    start: usize,
+    text_start: usize,
+    end: usize,
+    has_newline: bool,
+}
+
+impl Line {
+    /// The line the node starts on, all the way to the end (even if the node
+    /// stops before then.)
+    fn containing(node: &Node, source: &[u8]) -> Line {
+        let start = node.start_byte() - node.start_position().column;
+        let end = source[start..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map(|offset| start + offset)
+            .unwrap_or(source.len());
+
+        Line {
+            row: node.start_position().row,
+            start,
+            text_start: node.start_byte(),
+            end,
+            has_newline: end < source.len(),
+        }
+    }
+
+    /// The part of the line from `from` on, including the newline. Ranges
+    /// get glued together when we parse them, so we need the newlines to
+    /// keep lines apart.
+    fn range_from(&self, from: usize) -> Range {
+        let from = from.min(self.end);
+        let (end_byte, end_point) = if self.has_newline {
+            (self.end + 1, Point::new(self.row + 1, 0))
+        } else {
+            (self.end, Point::new(self.row, self.end - self.start))
+        };
+
+        Range {
+            start_byte: from,
+            end_byte,
+            start_point: Point::new(self.row, from - self.start),
+            end_point,
+        }
+    }
+}
+
+/// The lines that `node` is on, cut off where the node starts and ends.
+fn lines(node: Node, source: &[u8]) -> Vec<Line> {
+    let mut out = Vec::new();
+    let node_end = node.end_byte();
+    let mut row = node.start_position().row;
+    let mut line_start = node.start_byte() - node.start_position().column;
+    let mut from = node.start_byte();
+
+    while from < node_end {
+        let end = source[from..node_end]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map(|offset| from + offset);
+
+        out.push(Line {
+            row,
+            start: line_start,
+            text_start: from,
+            end: end.unwrap_or(node_end),
+            has_newline: end.is_some(),
+        });
+
+        match end {
+            Some(end) => {
+                row += 1;
+                line_start = end + 1;
+                from = end + 1;
+            }
+            None => break,
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+#[cfg_attr(
+    not(any(feature = "markdown", feature = "python", feature = "rust")),
+    allow(dead_code, unused_imports)
+)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser;
+
+    fn injected(language: Language, source: &str) -> Vec<(String, String)> {
+        let mut parser = Parser::new();
+        parser.set_language(&language.language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+
+        find(&language, &tree, source.as_bytes())
+            .into_iter()
+            .map(|injection| {
+                let text = injection
+                    .ranges()
+                    .iter()
+                    .map(|range| &source[range.start_byte..range.end_byte])
+                    .collect();
+
+                (injection.language().to_string(), text)
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn markdown_fences_use_their_info_string() {
+        assert_eq!(
+            injected(
+                Language::Markdown,
+                "# Hi\n\n```rs\nfn main() {}\n```\n\n```\nplain\n```\n\n```js {.numberLines}\nfoo()\n```\n"
+            ),
+            vec![
+                (String::from("rust"), String::from("fn main() {}\n")),
+                (String::from("javascript"), String::from("foo()\n")),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn python_doctests_skip_prompts_and_output() {
+        assert_eq!(
+            injected(
+                Language::Python,
+                "def f():\n    \"\"\"\n    >>> if True:\n    ...     f()\n    1\n    \"\"\"\n"
+            ),
+            vec![(String::from("python"), String::from("if True:\n    f()\n"))]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rust")]
+    fn rust_doc_comments_have_rust_blocks() {
+        assert_eq!(
+            injected(
+                Language::Rust,
+                "/// ```\n/// # use foo;\n/// foo();\n/// ```\n///\n/// ```text\n/// not rust\n/// ```\nfn foo() {}\n"
+            ),
+            vec![(String::from("rust"), String::from("use foo;\nfoo();\n"))]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "markdown")]
+    fn injected_ranges_point_into_the_host() {
+        let source = "Some code:\n\n```rust\nfn main() {}\n```\n";
+        let mut parser = Parser::new();
+        parser.set_language(&Language::Markdown.language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+
+        let injections = find(&Language::Markdown, &tree, source.as_bytes());
+        assert_eq!(injections[0].ranges()[0].start_point, Point::new(3, 0));
+    }
+}
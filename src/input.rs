@@ -0,0 +1,47 @@
+use crate::file_error::{ErrorKind, FileError};
+use anyhow::{Context, Result};
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Something to search: a file we found while walking, or whatever is on
+/// stdin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(PathBuf),
+    /// Stdin, with an optional name to show for it in output.
+    Stdin(Option<PathBuf>),
+}
+
+impl Input {
+    /// The path to show in output. For stdin, this is the label, if there is
+    /// one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Input::File(path) => Some(path),
+            Input::Stdin(label) => label.as_deref(),
+        }
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            Input::File(path) => fs::read(path),
+            Input::Stdin(_) => {
+                let mut source = Vec::new();
+                io::stdin().read_to_end(&mut source).map(|_| source)
+            }
+        }
+        .with_context(|| FileError::new(ErrorKind::Read, self.path()))
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::File(path) => path.display().fmt(f),
+            Input::Stdin(Some(label)) => label.display().fmt(f),
+            Input::Stdin(None) => f.write_str("stdin"),
+        }
+    }
+}
@@ -1,58 +1,155 @@
+use crate::grammar::{self, Grammar};
 use anyhow::{anyhow, Error, Result};
+use itertools::Itertools;
+use std::fmt::{self, Display};
 use std::str::FromStr;
-use strum::{IntoEnumIterator, VariantNames};
-use strum_macros::{Display, EnumIter, FromRepr, VariantNames};
+use strum::IntoEnumIterator;
+use strum_macros::{AsRefStr, EnumIter};
 
-#[derive(Display, FromRepr, EnumIter, VariantNames, PartialEq, Eq, Hash, Debug)]
+/// Every language that can be compiled in. Each one has a cargo feature with
+/// the same name (all on by default.) We keep this list separately from the
+/// enum so we can still tell people what they're missing when they ask for a
+/// language that isn't compiled in.
+const BUILTIN_FEATURES: &[&str] = &[
+    "c",
+    "cpp",
+    "cuda",
+    "elixir",
+    "elm",
+    "go",
+    "haskell",
+    "java",
+    "javascript",
+    "markdown",
+    "markdown_inline",
+    "nix",
+    "php",
+    "php_only",
+    "powershell",
+    "python",
+    "ruby",
+    "rust",
+    "sass",
+    "tsx",
+    "typescript",
+];
+
+#[derive(AsRefStr, EnumIter, PartialEq, Eq, Hash, Debug, Clone)]
 #[strum(serialize_all = "lowercase")]
 pub enum Language {
+    #[cfg(feature = "c")]
     C,
+    #[cfg(feature = "cpp")]
     Cpp,
+    #[cfg(feature = "cuda")]
     Cuda,
+    #[cfg(feature = "elixir")]
     Elixir,
+    #[cfg(feature = "elm")]
     Elm,
+    #[cfg(feature = "go")]
     Go,
+    #[cfg(feature = "haskell")]
     Haskell,
+    #[cfg(feature = "java")]
     Java,
+    #[cfg(feature = "javascript")]
     JavaScript,
+    #[cfg(feature = "markdown")]
     Markdown,
+    /// The grammar for the inline parts of Markdown (emphasis, links, and so
+    /// on.) The `markdown` grammar leaves these as `inline` nodes.
+    #[cfg(feature = "markdown_inline")]
+    #[strum(serialize = "markdown_inline")]
+    MarkdownInline,
+    #[cfg(feature = "nix")]
     Nix,
+    #[cfg(feature = "php")]
     Php,
+    /// PHP without any HTML around it.
+    #[cfg(feature = "php_only")]
+    #[strum(serialize = "php_only")]
+    PhpOnly,
+    #[cfg(feature = "powershell")]
     PowerShell,
+    #[cfg(feature = "python")]
     Python,
+    #[cfg(feature = "ruby")]
     Ruby,
+    #[cfg(feature = "rust")]
     Rust,
+    #[cfg(feature = "sass")]
     Sass,
+    #[cfg(feature = "tsx")]
+    Tsx,
+    #[cfg(feature = "typescript")]
     TypeScript,
+    /// A grammar loaded at runtime. See the `grammar` module.
+    #[strum(disabled)]
+    Loaded(Grammar),
 }
 
 impl Language {
+    /// Every language we know about, including loaded grammars, sorted by
+    /// name.
     pub fn all() -> Vec<Language> {
-        Language::iter().collect()
+        Language::iter_builtin()
+            .chain(grammar::registered().into_iter().map(Language::Loaded))
+            .sorted_by_key(|language| language.to_string())
+            .collect()
+    }
+
+    /// The languages that are compiled in. This depends on which cargo
+    /// features are enabled.
+    pub fn iter_builtin() -> impl Iterator<Item = Language> {
+        Language::iter()
     }
 
     pub fn language(&self) -> tree_sitter::Language {
-        unsafe {
-            match self {
-                Language::C => tree_sitter_c(),
-                Language::Cpp => tree_sitter_cpp(),
-                Language::Cuda => tree_sitter_cuda(),
-                Language::Elixir => tree_sitter_elixir(),
-                Language::Elm => tree_sitter_elm(),
-                Language::Go => tree_sitter_go(),
-                Language::Haskell => tree_sitter_haskell(),
-                Language::Java => tree_sitter_java(),
-                Language::JavaScript => tree_sitter_javascript(),
-                Language::Markdown => tree_sitter_markdown(),
-                Language::Nix => tree_sitter_nix(),
-                Language::Php => tree_sitter_php(),
-                Language::PowerShell => tree_sitter_powershell(),
-                Language::Python => tree_sitter_python(),
-                Language::Ruby => tree_sitter_ruby(),
-                Language::Rust => tree_sitter_rust(),
-                Language::Sass => tree_sitter_scss(),
-                Language::TypeScript => tree_sitter_typescript(),
-            }
+        match self {
+            #[cfg(feature = "c")]
+            Language::C => unsafe { tree_sitter_c() },
+            #[cfg(feature = "cpp")]
+            Language::Cpp => unsafe { tree_sitter_cpp() },
+            #[cfg(feature = "cuda")]
+            Language::Cuda => unsafe { tree_sitter_cuda() },
+            #[cfg(feature = "elixir")]
+            Language::Elixir => unsafe { tree_sitter_elixir() },
+            #[cfg(feature = "elm")]
+            Language::Elm => unsafe { tree_sitter_elm() },
+            #[cfg(feature = "go")]
+            Language::Go => unsafe { tree_sitter_go() },
+            #[cfg(feature = "haskell")]
+            Language::Haskell => unsafe { tree_sitter_haskell() },
+            #[cfg(feature = "java")]
+            Language::Java => unsafe { tree_sitter_java() },
+            #[cfg(feature = "javascript")]
+            Language::JavaScript => unsafe { tree_sitter_javascript() },
+            #[cfg(feature = "markdown")]
+            Language::Markdown => unsafe { tree_sitter_markdown() },
+            #[cfg(feature = "markdown_inline")]
+            Language::MarkdownInline => unsafe { tree_sitter_markdown_inline() },
+            #[cfg(feature = "nix")]
+            Language::Nix => unsafe { tree_sitter_nix() },
+            #[cfg(feature = "php")]
+            Language::Php => unsafe { tree_sitter_php() },
+            #[cfg(feature = "php_only")]
+            Language::PhpOnly => unsafe { tree_sitter_php_only() },
+            #[cfg(feature = "powershell")]
+            Language::PowerShell => unsafe { tree_sitter_powershell() },
+            #[cfg(feature = "python")]
+            Language::Python => unsafe { tree_sitter_python() },
+            #[cfg(feature = "ruby")]
+            Language::Ruby => unsafe { tree_sitter_ruby() },
+            #[cfg(feature = "rust")]
+            Language::Rust => unsafe { tree_sitter_rust() },
+            #[cfg(feature = "sass")]
+            Language::Sass => unsafe { tree_sitter_scss() },
+            #[cfg(feature = "tsx")]
+            Language::Tsx => unsafe { tree_sitter_tsx() },
+            #[cfg(feature = "typescript")]
+            Language::TypeScript => unsafe { tree_sitter_typescript() },
+            Language::Loaded(grammar) => grammar.language(),
         }
     }
 
@@ -62,24 +159,89 @@ impl Language {
 
     pub fn name_for_types_builder(&self) -> &str {
         match self {
+            #[cfg(feature = "c")]
             Language::C => "c",
+            #[cfg(feature = "cpp")]
             Language::Cpp => "cpp",
+            #[cfg(feature = "cuda")]
             Language::Cuda => "cuda",
+            #[cfg(feature = "elixir")]
             Language::Elixir => "elixir",
+            #[cfg(feature = "elm")]
             Language::Elm => "elm",
+            #[cfg(feature = "go")]
             Language::Go => "go",
+            #[cfg(feature = "haskell")]
             Language::Haskell => "haskell",
+            #[cfg(feature = "java")]
             Language::Java => "java",
+            #[cfg(feature = "javascript")]
             Language::JavaScript => "js",
+            #[cfg(feature = "markdown")]
             Language::Markdown => "markdown",
+            #[cfg(feature = "markdown_inline")]
+            Language::MarkdownInline => "markdowninline",
+            #[cfg(feature = "nix")]
             Language::Nix => "nix",
+            #[cfg(feature = "php")]
             Language::Php => "php",
+            #[cfg(feature = "php_only")]
+            Language::PhpOnly => "phponly",
+            #[cfg(feature = "python")]
             Language::Python => "py",
+            #[cfg(feature = "ruby")]
             Language::Ruby => "ruby",
+            #[cfg(feature = "rust")]
             Language::Rust => "rust",
+            #[cfg(feature = "sass")]
             Language::Sass => "sass",
+            #[cfg(feature = "tsx")]
+            Language::Tsx => "tsx",
+            #[cfg(feature = "typescript")]
             Language::TypeScript => "ts",
+            #[cfg(feature = "powershell")]
             Language::PowerShell => "powershell",
+            Language::Loaded(grammar) => grammar.type_name(),
+        }
+    }
+
+    /// Find a language by a name people write in files instead of on the
+    /// command line, like the tag on a Markdown code block or a filetype in a
+    /// Vim modeline. That can be the name we use for it, the name of its file
+    /// type, or a common abbreviation, in any case.
+    pub fn from_alias(name: &str) -> Option<Language> {
+        let name = name.to_lowercase();
+        let name = match name.as_str() {
+            "rs" => "rust",
+            "jsx" | "javascriptreact" => "javascript",
+            "typescriptreact" => "tsx",
+            "c++" => "cpp",
+            "golang" => "go",
+            "hs" => "haskell",
+            "ex" | "exs" => "elixir",
+            "rb" => "ruby",
+            "ps1" => "powershell",
+            "scss" => "sass",
+            other => other,
+        };
+
+        if let Ok(language) = Language::from_str(name) {
+            return Some(language);
+        }
+
+        Language::all()
+            .into_iter()
+            .find(|language| language.name_for_types_builder() == name)
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Language::Loaded(grammar) => grammar.fmt(f),
+            // unreachable if every grammar feature is turned off
+            #[allow(unreachable_patterns)]
+            builtin => f.write_str(builtin.as_ref()),
         }
     }
 }
@@ -88,38 +250,65 @@ impl FromStr for Language {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let languages = Language::VARIANTS;
-        languages
-            .binary_search(&s)
-            .map(|idx| Language::from_repr(idx).unwrap())
-            .map_err(|_| {
-                anyhow!(
-                    "unknown language {}. Try one of: {}",
-                    s,
-                    languages.join(", ")
-                )
-            })
+        let languages = Language::all();
+        match languages.iter().find(|language| language.to_string() == s) {
+            Some(language) => Ok(language.clone()),
+            None if BUILTIN_FEATURES.contains(&s) => Err(anyhow!(
+                "this tree-grepper was built without support for {}. Rebuild it with the `{}` feature to use it.",
+                s,
+                s
+            )),
+            None => Err(anyhow!(
+                "unknown language {}. Try one of: {}",
+                s,
+                languages.iter().join(", ")
+            )),
+        }
     }
 }
 
 extern "C" {
+    #[cfg(feature = "c")]
     fn tree_sitter_c() -> tree_sitter::Language;
+    #[cfg(feature = "cpp")]
     fn tree_sitter_cpp() -> tree_sitter::Language;
+    #[cfg(feature = "cuda")]
     fn tree_sitter_cuda() -> tree_sitter::Language;
+    #[cfg(feature = "elixir")]
     fn tree_sitter_elixir() -> tree_sitter::Language;
+    #[cfg(feature = "elm")]
     fn tree_sitter_elm() -> tree_sitter::Language;
+    #[cfg(feature = "go")]
     fn tree_sitter_go() -> tree_sitter::Language;
+    #[cfg(feature = "haskell")]
     fn tree_sitter_haskell() -> tree_sitter::Language;
+    #[cfg(feature = "java")]
     fn tree_sitter_java() -> tree_sitter::Language;
+    #[cfg(feature = "javascript")]
     fn tree_sitter_javascript() -> tree_sitter::Language;
+    #[cfg(feature = "markdown")]
     fn tree_sitter_markdown() -> tree_sitter::Language;
+    #[cfg(feature = "markdown_inline")]
+    fn tree_sitter_markdown_inline() -> tree_sitter::Language;
+    #[cfg(feature = "nix")]
     fn tree_sitter_nix() -> tree_sitter::Language;
+    #[cfg(feature = "php")]
     fn tree_sitter_php() -> tree_sitter::Language;
+    #[cfg(feature = "php_only")]
+    fn tree_sitter_php_only() -> tree_sitter::Language;
+    #[cfg(feature = "powershell")]
     fn tree_sitter_powershell() -> tree_sitter::Language;
+    #[cfg(feature = "python")]
     fn tree_sitter_python() -> tree_sitter::Language;
+    #[cfg(feature = "ruby")]
     fn tree_sitter_ruby() -> tree_sitter::Language;
+    #[cfg(feature = "rust")]
     fn tree_sitter_rust() -> tree_sitter::Language;
+    #[cfg(feature = "sass")]
     fn tree_sitter_scss() -> tree_sitter::Language;
+    #[cfg(feature = "tsx")]
+    fn tree_sitter_tsx() -> tree_sitter::Language;
+    #[cfg(feature = "typescript")]
     fn tree_sitter_typescript() -> tree_sitter::Language;
 }
 
@@ -132,8 +321,24 @@ mod tests {
         // Note: this will hide results if there are multiple failures. It's
         // something that could be worked around but I don't think it is right
         // now. If it bothers you in the future, feel free to take a stab at it!
-        Language::iter()
-            .for_each(|lang| assert_eq!(Language::from_str(&lang.to_string()).unwrap(), lang))
+        Language::iter().for_each(|lang| {
+            let name = lang.to_string();
+            assert_eq!(Language::from_str(&name).unwrap(), lang)
+        })
+    }
+
+    #[test]
+    fn builtins_have_features() {
+        Language::iter().for_each(|lang| assert!(BUILTIN_FEATURES.contains(&lang.as_ref())))
+    }
+
+    #[test]
+    #[cfg(all(feature = "cpp", feature = "python"))]
+    fn aliases_find_languages() {
+        assert_eq!(Language::from_alias("C++"), Some(Language::Cpp));
+        assert_eq!(Language::from_alias("py"), Some(Language::Python));
+        assert_eq!(Language::from_alias("Python"), Some(Language::Python));
+        assert_eq!(Language::from_alias("brainfuck"), None);
     }
 
     #[test]
@@ -145,16 +350,16 @@ mod tests {
 
     #[test]
     fn language_list_should_be_sorted() {
-        use itertools::Itertools;
-        // sorted elements needed by binary_search in FromStr
+        // sorted so that `--languages` is easy to read
         // TODO: use is_sorted: https://github.com/rust-lang/rust/issues/53485
-        assert!(Language::VARIANTS
-            .iter()
+        assert!(Language::iter()
+            .map(|lang| lang.to_string())
             .tuple_windows()
             .all(|(a, b)| a <= b));
     }
 
     #[test]
+    #[cfg(feature = "elm")]
     fn parse_query_problem() {
         // tree-grepper 1.0 just printed the error struct when problems like
         // this happened. This test is just here to make sure we take a slightly
@@ -0,0 +1,50 @@
+//! Search code for structure instead of strings, using tree-sitter queries.
+//!
+//! This is the engine behind the `tree-grepper` command-line tool. Most uses
+//! should start with [`Searcher::builder`].
+
+pub mod encoding;
+pub mod extractor;
+pub mod file_error;
+pub mod file_types;
+pub mod grammar;
+pub mod input;
+pub mod language;
+pub mod limits;
+pub mod listing;
+pub mod skip;
+pub mod stats;
+
+// the command-line tool needs these, but they aren't part of the library's
+// API and can change at any time.
+#[doc(hidden)]
+pub mod count;
+#[doc(hidden)]
+pub mod notebook;
+#[doc(hidden)]
+pub mod printer;
+#[doc(hidden)]
+pub mod rewriter;
+#[doc(hidden)]
+pub mod tree_view;
+
+mod detect;
+mod extractor_chooser;
+mod generated;
+mod gitattributes;
+mod grammar_compiler;
+mod injection;
+mod pipeline;
+mod query_file;
+mod searcher;
+
+pub use encoding::Encoding;
+pub use extractor::{ContextLine, ExtractedFile, ExtractedMatch, Extractor};
+pub use file_error::{ErrorKind, FileError};
+pub use file_types::FileTypes;
+pub use input::Input;
+pub use language::Language;
+pub use listing::{ListFiles, ListedFile};
+pub use searcher::{Searcher, SearcherBuilder};
+pub use skip::{SkipReason, Skipped};
+pub use stats::Stats;
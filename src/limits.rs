@@ -0,0 +1,221 @@
+use anyhow::{bail, Context, Result};
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+
+/// How long we're willing to spend on a file, and on the whole search. There
+/// are no limits by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    parse_timeout: Option<Duration>,
+    query_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Give up on parsing anything that takes longer than this.
+    pub fn parse_timeout(self, timeout: Duration) -> Limits {
+        Limits {
+            parse_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Give up on running a query over a tree if it takes longer than this.
+    pub fn query_timeout(self, timeout: Duration) -> Limits {
+        Limits {
+            query_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Give up on everything at this point.
+    pub fn deadline(self, deadline: Instant) -> Limits {
+        Limits {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Whether we should have stopped everything already.
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub(crate) fn is_unlimited(&self, stage: Stage) -> bool {
+        self.deadline.is_none() && self.timeout(stage).is_none()
+    }
+
+    /// Start timing a parse or a query. Check the watch from tree-sitter's
+    /// progress callbacks, then `finish` it to find out if we ran out of time.
+    pub(crate) fn watch(&self, stage: Stage) -> Watch {
+        Watch {
+            started: Instant::now(),
+            timeout: self.timeout(stage),
+            stage,
+            deadline: self.deadline,
+            stopped: Cell::new(None),
+        }
+    }
+
+    fn timeout(&self, stage: Stage) -> Option<Duration> {
+        match stage {
+            Stage::Parse => self.parse_timeout,
+            Stage::Query => self.query_timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    Parse,
+    Query,
+}
+
+pub(crate) struct Watch {
+    started: Instant,
+    timeout: Option<Duration>,
+    stage: Stage,
+    deadline: Option<Instant>,
+    stopped: Cell<Option<TimedOut>>,
+}
+
+impl Watch {
+    /// Whether to stop now. tree-sitter calls progress callbacks often, so
+    /// this has to be cheap.
+    pub(crate) fn check(&self) -> bool {
+        let now = Instant::now();
+
+        let stopped = if self.deadline.is_some_and(|deadline| now >= deadline) {
+            Some(TimedOut::Deadline)
+        } else {
+            match (self.timeout, self.stage) {
+                (Some(timeout), Stage::Parse) if now - self.started > timeout => {
+                    Some(TimedOut::Parse(timeout))
+                }
+                (Some(timeout), Stage::Query) if now - self.started > timeout => {
+                    Some(TimedOut::Query(timeout))
+                }
+                _ => None,
+            }
+        };
+
+        match stopped {
+            Some(timed_out) => {
+                self.stopped.set(Some(timed_out));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An error if `check` ever said to stop.
+    pub(crate) fn finish(&self) -> Result<(), TimedOut> {
+        match self.stopped.take() {
+            Some(timed_out) => Err(timed_out),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The error we give when we stop working on a file because it took too long.
+/// This doesn't mean anything is wrong with the file, so the searcher counts
+/// these as skipped files instead of errors. Look for it in an error's
+/// `chain()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimedOut {
+    Parse(Duration),
+    Query(Duration),
+    Deadline,
+}
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimedOut::Parse(timeout) => write!(f, "parsing took longer than {:?}", timeout),
+            TimedOut::Query(timeout) => write!(f, "querying took longer than {:?}", timeout),
+            TimedOut::Deadline => f.write_str("the deadline for the search passed"),
+        }
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Find out if `error` happened because something took too long.
+pub fn timed_out(error: &anyhow::Error) -> Option<&TimedOut> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<TimedOut>())
+}
+
+/// Parse a duration like `500ms`, `30s`, `5m`, `2h`, or `7d`. A number without
+/// a unit is seconds.
+pub fn parse_duration(raw: &str) -> Result<Duration> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("expected a duration like `30s`, but got `{}`", raw))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        "d" => number * 60.0 * 60.0 * 24.0,
+        other => bail!(
+            "unknown unit `{}` in `{}`. Try ms, s, m, h, or d.",
+            other,
+            raw
+        ),
+    };
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_have_units() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604_800));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("3w").is_err());
+    }
+
+    #[test]
+    fn watches_stop_after_their_timeout() {
+        let limits = Limits::new().parse_timeout(Duration::ZERO);
+        let watch = limits.watch(Stage::Parse);
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert!(watch.check());
+        assert_eq!(watch.finish(), Err(TimedOut::Parse(Duration::ZERO)));
+
+        let watch = limits.watch(Stage::Query);
+        assert!(!watch.check());
+        assert_eq!(watch.finish(), Ok(()));
+    }
+
+    #[test]
+    fn deadlines_stop_everything() {
+        let limits = Limits::new().deadline(Instant::now());
+
+        assert!(limits.deadline_passed());
+        let watch = limits.watch(Stage::Query);
+        assert!(watch.check());
+        assert_eq!(watch.finish(), Err(TimedOut::Deadline));
+    }
+}
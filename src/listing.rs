@@ -0,0 +1,47 @@
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFiles {
+    WithMatches,
+    WithoutMatch,
+}
+
+/// A file that did (or didn't) match, for when you only need the paths.
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListedFile {
+    file: Option<PathBuf>,
+    file_type: String,
+}
+
+impl ListedFile {
+    pub fn new(file: Option<PathBuf>, file_type: String) -> ListedFile {
+        ListedFile { file, file_type }
+    }
+
+    /// The file that did (or didn't) match, if it was a file.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The name of the language the file was parsed as. Files parsed as
+    /// more than one language have the first one that matched (or the first
+    /// one, if none did.)
+    pub fn file_type(&self) -> &str {
+        &self.file_type
+    }
+}
+
+impl Display for ListedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // see the note in `LinesPrinter` about unwrapping
+        let filename = self
+            .file
+            .as_ref()
+            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
+            .unwrap_or("NO FILE");
+
+        writeln!(f, "{}", filename)
+    }
+}
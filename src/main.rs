@@ -1,68 +1,102 @@
 mod cli;
-mod extractor;
-mod extractor_chooser;
-mod language;
-mod tree_view;
 
 use anyhow::{bail, Context, Result};
-use cli::{Invocation, QueryFormat, QueryOpts, TreeOpts};
-use crossbeam::channel;
-use language::Language;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use cli::{ColorChoice, Invocation, QueryFormat, QueryOpts, RewriteOpts, TreeOpts};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::Serialize;
+use std::cell::RefCell;
 use std::env;
+use std::fmt::Display;
 use std::fs;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use tree_grepper::count::FileCount;
+use tree_grepper::extractor::Extractor;
+use tree_grepper::file_error::{ErrorKind, FileError};
+use tree_grepper::file_types::FileTypes;
+use tree_grepper::input::Input;
+use tree_grepper::limits::{self, TimedOut};
+use tree_grepper::printer::LinesPrinter;
+use tree_grepper::skip::Skipped;
+use tree_grepper::{grammar, notebook, rewriter, tree_view, Language, Stats};
 use tree_sitter::Parser;
 
-#[global_allocator]
-static ALLOCATOR: bump_alloc::BumpAlloc = bump_alloc::BumpAlloc::new();
-
 fn main() {
-    let mut buffer = BufWriter::new(io::stdout());
-
-    if let Err(error) = try_main(env::args().collect(), &mut buffer) {
-        if let Some(err) = error.downcast_ref::<io::Error>() {
-            // a broken pipe is totally normal and fine. It's what we get when
-            // we pipe to something like `head` that only takes a certain number
-            // of lines.
-            if err.kind() == io::ErrorKind::BrokenPipe {
-                std::process::exit(0);
-            }
-        }
+    // Results are written as soon as they're ready. When someone's watching,
+    // we want them to show up right away, so we only buffer output when it's
+    // going to a file or another program.
+    let stdout = io::stdout();
+    let is_terminal = stdout.is_terminal();
+    let mut buffer: Box<dyn Write> = if is_terminal {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(BufWriter::new(stdout.lock()))
+    };
 
-        if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
-            // Clap errors (--help or misuse) are already well-formatted,
-            // so we don't have to do any additional work.
-            eprint!("{}", clap_error);
-            if clap_error.kind() == clap::error::ErrorKind::DisplayHelp
-                || clap_error.kind() == clap::error::ErrorKind::DisplayVersion
-            {
-                std::process::exit(0);
-            }
-        } else {
-            eprintln!("{:?}", error);
+    let result = try_main(env::args().collect(), &mut buffer, is_terminal).and_then(|had_errors| {
+        buffer
+            .flush()
+            .context("failed to flush output")
+            .map(|()| had_errors)
+    });
+
+    match result {
+        Ok(false) => (),
+        // we kept going past some problems, and already reported each of
+        // them, but we shouldn't look like everything went fine.
+        Ok(true) => std::process::exit(2),
+        Err(error) => exit_with_error(error),
+    }
+}
+
+fn exit_with_error(error: anyhow::Error) -> ! {
+    if let Some(err) = error.downcast_ref::<io::Error>() {
+        // a broken pipe is totally normal and fine. It's what we get when
+        // we pipe to something like `head` that only takes a certain number
+        // of lines.
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            std::process::exit(0);
         }
+    }
 
-        std::process::exit(1);
+    if let Some(clap_error) = error.downcast_ref::<clap::Error>() {
+        // Clap errors (--help or misuse) are already well-formatted,
+        // so we don't have to do any additional work.
+        eprint!("{}", clap_error);
+        if clap_error.kind() == clap::error::ErrorKind::DisplayHelp
+            || clap_error.kind() == clap::error::ErrorKind::DisplayVersion
+        {
+            std::process::exit(0);
+        }
+    } else {
+        eprintln!("{:?}", error);
     }
 
-    buffer.flush().expect("failed to flush buffer!");
+    std::process::exit(1);
 }
 
-fn try_main(args: Vec<String>, out: impl Write) -> Result<()> {
+/// Do what the arguments say. The result is whether any errors were reported
+/// along the way without stopping us.
+fn try_main(args: Vec<String>, out: impl Write, is_terminal: bool) -> Result<bool> {
     let invocation = Invocation::from_args(args)
         .context("couldn't get a valid configuration from the command-line options")?;
 
     match invocation {
-        Invocation::DoQuery(query_opts) => {
-            do_query(query_opts, out).context("couldn't perform the query")
-        }
-        Invocation::ShowLanguages => {
-            show_languages(out).context("couldn't show the list of languages")
-        }
-        Invocation::ShowTree(tree_opts) => {
-            show_tree(tree_opts, out).context("couldn't show the tree")
-        }
+        Invocation::DoQuery(query_opts) => do_query(query_opts, out, is_terminal)
+            .context("couldn't perform the query")
+            .map(|stats| stats.errors() > 0),
+        Invocation::DoRewrite(rewrite_opts) => do_rewrite(rewrite_opts, out)
+            .context("couldn't perform the rewrite")
+            .map(|()| false),
+        Invocation::ShowLanguages => show_languages(out)
+            .context("couldn't show the list of languages")
+            .map(|()| false),
+        Invocation::ShowTree(tree_opts) => show_tree(tree_opts, out)
+            .context("couldn't show the tree")
+            .map(|()| false),
+        Invocation::ShowTypes(file_types) => show_types(file_types, out)
+            .context("couldn't show the list of file types")
+            .map(|()| false),
     }
 }
 
@@ -74,122 +108,435 @@ fn show_languages(mut out: impl Write) -> Result<()> {
     Ok(())
 }
 
+fn show_types(file_types: FileTypes, mut out: impl Write) -> Result<()> {
+    for (language, globs) in file_types.globs()? {
+        if globs.is_empty() {
+            writeln!(out, "{}:", language)
+        } else {
+            writeln!(out, "{}: {}", language, globs.join(", "))
+        }
+        .context("couldn't print a file type")?;
+    }
+
+    Ok(())
+}
+
 fn show_tree(opts: TreeOpts, out: impl Write) -> Result<()> {
-    let source = fs::read_to_string(opts.path).context("could not read target file")?;
+    let source = opts.encoding.decode(opts.input.read()?);
 
     let mut parser = Parser::new();
-    parser
-        .set_language(&opts.language.language())
-        .context("could not set language")?;
+    grammar::set_parser_language(&mut parser, &opts.language.language())?;
 
     let tree = parser
-        .parse(&source, None)
+        .parse(source.text(), None)
         .context("could not parse tree")?;
 
-    tree_view::tree_view(&tree, source.as_bytes(), out)
-}
-
-fn do_query(opts: QueryOpts, mut out: impl Write) -> Result<()> {
-    // You might think "why not use ParallelBridge here?" Well, the quick answer
-    // is that I benchmarked it and having things separated here and handling
-    // their own errors actually speeds up this part of the code by like 20%!
-    let items: Vec<ignore::DirEntry> =
-        find_files(&opts).context("had a problem while walking the filesystem")?;
-
-    let chooser = opts
-        .extractor_chooser()
-        .context("couldn't construct a filetype matcher")?;
-
-    let mut extracted_files = items
-        .par_iter()
-        .filter_map(|entry| {
-            chooser
-                .extractor_for(entry)
-                .map(|extractor| (entry, extractor))
-        })
-        .map_init(Parser::new, |parser, (entry, extractor)| {
-            extractor
-                .extract_from_file(entry.path(), parser)
-                .with_context(|| {
-                    format!("could not extract matches from {}", entry.path().display())
-                })
-        })
-        .filter_map(|result_containing_option| match result_containing_option {
-            Ok(None) => None,
-            Ok(Some(extraction)) => Some(Ok(extraction)),
-            Err(err) => Some(Err(err)),
-        })
-        .collect::<Result<Vec<extractor::ExtractedFile>>>()
-        .context("couldn't extract matches from files")?;
-
-    if opts.sort {
-        extracted_files.sort()
+    tree_view::tree_view(&tree, &source, out)
+}
+
+fn do_query(opts: QueryOpts, mut out: impl Write, is_terminal: bool) -> Result<Stats> {
+    let searcher = &opts.searcher;
+
+    let stats = if let Some(which) = opts.list_files {
+        write_results(
+            &opts.format,
+            &mut out,
+            None,
+            opts.json_errors,
+            write_display,
+            |emit, report| searcher.list_files(which, emit, report),
+        )?
+    } else if let Some(by) = opts.count {
+        write_results(
+            &opts.format,
+            &mut out,
+            None,
+            opts.json_errors,
+            write_display,
+            |emit, report| {
+                searcher.search(
+                    |extracted_file| emit(FileCount::new(&extracted_file, by)),
+                    report,
+                )
+            },
+        )?
+    } else {
+        let color = match opts.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // see https://no-color.org
+            ColorChoice::Auto => {
+                is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        };
+        let printer = LinesPrinter::new(color, opts.heading, opts.hyperlinks);
+
+        // like ripgrep, files are separated by a blank line when there are
+        // headings, or by `--` (like grep) when we're showing context.
+        let separator = if opts.heading {
+            Some("")
+        } else if searcher.has_context() {
+            Some("--")
+        } else {
+            None
+        };
+
+        write_results(
+            &opts.format,
+            &mut out,
+            separator,
+            opts.json_errors,
+            |out, extracted_file| write!(out, "{}", printer.display(extracted_file)),
+            |emit, report| searcher.search(emit, report),
+        )?
+    };
+
+    // stats go to stderr so they can't end up in the middle of JSON output,
+    // but they should still come after the results.
+    if opts.stats {
+        out.flush().context("failed to flush output")?;
+        eprint!("{}", stats);
+    }
+
+    if stats.deadline_passed() {
+        eprintln!("the deadline passed before the search finished, so some files weren't searched");
+    }
+
+    Ok(stats)
+}
+
+/// Tell someone about a problem that didn't stop the search.
+fn report(error: anyhow::Error) -> Result<()> {
+    if let Some(skipped) = error.downcast_ref::<Skipped>() {
+        eprintln!("{}", skipped);
+        return Ok(());
+    }
+
+    match (limits::timed_out(&error), error.downcast_ref::<FileError>()) {
+        // taking too long isn't the file's fault, so it shouldn't read like
+        // an error.
+        (Some(timed_out), Some(file_error)) => {
+            eprintln!("skipped {}: {}", file_name(file_error), timed_out)
+        }
+        _ => eprintln!("{:#}", error),
     }
+    Ok(())
+}
+
+fn file_name(file_error: &FileError) -> String {
+    match file_error.path() {
+        Some(path) => path.display().to_string(),
+        None => String::from("stdin"),
+    }
+}
 
-    match opts.format {
+fn write_display<T: Display>(out: &mut dyn Write, item: &T) -> io::Result<()> {
+    write!(out, "{}", item)
+}
+
+/// Write results in the requested format as `run` finds them. Lines output
+/// uses `write_lines` for each result, with `separator` on its own line
+/// between them if there is one. Errors go to stderr, unless `error_records`
+/// is set and we're writing JSON, in which case they go in the output along
+/// with the results (and everything gets tagged with which one it is.)
+fn write_results<T, L, R>(
+    format: &QueryFormat,
+    mut out: impl Write,
+    separator: Option<&str>,
+    error_records: bool,
+    write_lines: L,
+    run: R,
+) -> Result<Stats>
+where
+    T: Serialize,
+    L: Fn(&mut dyn Write, &T) -> io::Result<()>,
+    R: FnOnce(
+        &mut dyn FnMut(T) -> Result<()>,
+        &mut dyn FnMut(anyhow::Error) -> Result<()>,
+    ) -> Result<Stats>,
+{
+    let stats = match format {
         QueryFormat::Lines => {
-            for extracted_file in extracted_files {
-                write!(out, "{}", extracted_file).context("could not write lines")?;
-            }
+            let mut first = true;
+
+            run(
+                &mut |item| {
+                    if let (Some(separator), false) = (separator, first) {
+                        writeln!(out, "{}", separator).context("could not write separator")?;
+                    }
+                    first = false;
+
+                    write_lines(&mut out, &item).context("could not write lines")
+                },
+                &mut report,
+            )?
         }
 
         QueryFormat::Json => {
-            serde_json::to_writer(out, &extracted_files).context("could not write JSON output")?;
+            write_json_array(&mut serde_json::Serializer::new(out), error_records, run)?
         }
 
         QueryFormat::JsonLines => {
-            for extracted_file in extracted_files {
-                writeln!(
-                    out,
-                    "{}",
-                    serde_json::to_string(&extracted_file)
-                        .context("could not write JSON output")?
-                )
-                .context("could not write line")?;
-            }
+            // results and errors both go to `out`, so they have to share it.
+            let out = RefCell::new(out);
+
+            run(
+                &mut |item| {
+                    if error_records {
+                        write_json_line(&out, &Record::Match(item))
+                    } else {
+                        write_json_line(&out, &item)
+                    }
+                },
+                &mut |error| {
+                    if error_records {
+                        write_json_line(&out, &Record::<T>::error(&error))
+                    } else {
+                        report(error)
+                    }
+                },
+            )?
         }
 
         QueryFormat::PrettyJson => {
-            serde_json::to_writer_pretty(out, &extracted_files)
-                .context("could not write JSON output")?;
+            write_json_array(&mut serde_json::Serializer::pretty(out), error_records, run)?
         }
-    }
+    };
 
-    Ok(())
+    Ok(stats)
+}
+
+fn write_json_line(out: &RefCell<impl Write>, value: &impl Serialize) -> Result<()> {
+    let line = serde_json::to_string(value).context("could not write JSON output")?;
+    writeln!(out.borrow_mut(), "{}", line).context("could not write line")
 }
 
-fn find_files(opts: &QueryOpts) -> Result<Vec<ignore::DirEntry>> {
-    let mut builder = match opts.paths.split_first() {
-        Some((first, rest)) => {
-            let mut builder = ignore::WalkBuilder::new(first);
-            for path in rest {
-                builder.add(path);
+/// Write results as a JSON array, one element at a time. This produces the
+/// same output as serializing a `Vec` all at once, but we don't have to wait
+/// for every file to be done before we start.
+fn write_json_array<W, F, T, R>(
+    serializer: &mut serde_json::Serializer<W, F>,
+    error_records: bool,
+    run: R,
+) -> Result<Stats>
+where
+    W: Write,
+    F: serde_json::ser::Formatter,
+    T: Serialize,
+    R: FnOnce(
+        &mut dyn FnMut(T) -> Result<()>,
+        &mut dyn FnMut(anyhow::Error) -> Result<()>,
+    ) -> Result<Stats>,
+{
+    let seq = RefCell::new(
+        serializer
+            .serialize_seq(None)
+            .context("could not write JSON output")?,
+    );
+
+    let stats = run(
+        &mut |item| {
+            if error_records {
+                write_json_element(&seq, &Record::Match(item))
+            } else {
+                write_json_element(&seq, &item)
+            }
+        },
+        &mut |error| {
+            if error_records {
+                write_json_element(&seq, &Record::<T>::error(&error))
+            } else {
+                report(error)
             }
+        },
+    )?;
+
+    seq.into_inner()
+        .end()
+        .context("could not write JSON output")?;
+    Ok(stats)
+}
+
+fn write_json_element<S>(seq: &RefCell<S>, value: &impl Serialize) -> Result<()>
+where
+    S: SerializeSeq<Error = serde_json::Error>,
+{
+    seq.borrow_mut()
+        .serialize_element(value)
+        .context("could not write JSON output")
+}
+
+/// A result or an error in JSON output with `--json-errors`, tagged with
+/// which one it is.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<T> {
+    Match(T),
+    Error(ErrorRecord),
+    Skipped(SkippedRecord),
+}
+
+impl<T> Record<T> {
+    /// An error record, or a skipped record if the error is that a file took
+    /// too long (or that we're explaining why we skipped it.)
+    fn error(error: &anyhow::Error) -> Record<T> {
+        if let Some(skipped) = error.downcast_ref::<Skipped>() {
+            return Record::Skipped(SkippedRecord {
+                path: Some(skipped.path().to_owned()),
+                reason: skipped.reason().to_string(),
+            });
+        }
+
+        if let Some(timed_out) = limits::timed_out(error) {
+            return Record::Skipped(SkippedRecord::timed_out(error, timed_out));
+        }
+
+        // the searcher says which file each error is about, and what kind
+        // of error it is, as context around the rest of the message.
+        let record = match error.downcast_ref::<FileError>() {
+            Some(file_error) => ErrorRecord {
+                path: file_error.path().map(|path| path.to_owned()),
+                kind: Some(file_error.kind()),
+                message: match walk_error_message(error) {
+                    Some(message) => message,
+                    None => error
+                        .chain()
+                        .skip(1)
+                        .map(|cause| cause.to_string())
+                        .collect::<Vec<_>>()
+                        .join(": "),
+                },
+            },
+            None => ErrorRecord {
+                path: None,
+                kind: None,
+                message: format!("{:#}", error),
+            },
+        };
+
+        Record::Error(record)
+    }
+}
+
+/// The message for an error from walking the filesystem, without the path.
+/// The walker puts the path in its messages (sometimes twice, like in
+/// "a: IO error for operation on a: ..."), but error records already have it.
+fn walk_error_message(error: &anyhow::Error) -> Option<String> {
+    let io_error = error.downcast_ref::<ignore::Error>()?.io_error()?;
+
+    let mut innermost: &dyn std::error::Error = io_error;
+    while let Some(source) = innermost.source() {
+        innermost = source;
+    }
+
+    Some(innermost.to_string())
+}
+
+#[derive(Serialize)]
+struct ErrorRecord {
+    path: Option<PathBuf>,
+    kind: Option<ErrorKind>,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct SkippedRecord {
+    path: Option<PathBuf>,
+    reason: String,
+}
+
+impl SkippedRecord {
+    fn timed_out(error: &anyhow::Error, timed_out: &TimedOut) -> SkippedRecord {
+        SkippedRecord {
+            path: error
+                .downcast_ref::<FileError>()
+                .and_then(|file_error| file_error.path())
+                .map(|path| path.to_owned()),
+            reason: timed_out.to_string(),
+        }
+    }
+}
+
+fn do_rewrite(opts: RewriteOpts, mut out: impl Write) -> Result<()> {
+    let encoding = opts.searcher.encoding();
+    let rewrite = |parser: &mut Parser, input: &Input, extractors: &[&Extractor]| {
+        // the code in a notebook is inside JSON strings, so we can't apply
+        // edits to it directly.
+        if input.path().is_some_and(notebook::is_notebook) {
+            return Ok(None);
+        }
 
-            builder
+        // edits are made to the decoded text, and the result is encoded
+        // again before it's written.
+        let source = encoding.decode(input.read()?);
+        let mut edits = Vec::new();
+        for extractor in extractors {
+            edits.extend(
+                extractor
+                    .edits_for_text(source.text(), parser, &opts.rewrite)
+                    .with_context(|| format!("could not rewrite {}", input))?,
+            );
+        }
+        if edits.is_empty() {
+            return Ok(None);
         }
-        None => bail!("I need at least one file or directory to walk!"),
+
+        let rewritten = rewriter::apply_edits(input.path(), source.text(), edits)?;
+        if rewritten == source.text() {
+            return Ok(None);
+        }
+
+        let bytes = source
+            .encode(&rewritten)
+            .with_context(|| FileError::new(ErrorKind::Encoding, input.path()))?;
+        Ok(Some((input.clone(), source, rewritten, bytes)))
     };
 
-    let (root_sender, receiver) = channel::unbounded();
-
-    builder
-        .git_ignore(opts.git_ignore)
-        .git_exclude(opts.git_ignore)
-        .git_global(opts.git_ignore)
-        .build_parallel()
-        .run(|| {
-            let sender = root_sender.clone();
-            Box::new(move |entry_result| match entry_result {
-                Ok(entry) => match sender.send(entry) {
-                    Ok(()) => ignore::WalkState::Continue,
-                    Err(_) => ignore::WalkState::Quit,
+    if opts.write {
+        // We compute every rewrite before touching the filesystem so that a
+        // problem in one file (like overlapping edits) doesn't leave the tree
+        // half-rewritten.
+        let mut rewritten_files = Vec::new();
+        opts.searcher
+            .run(
+                rewrite,
+                |rewritten_file| {
+                    rewritten_files.push(rewritten_file);
+                    Ok(())
                 },
-                Err(_) => ignore::WalkState::Quit,
-            })
-        });
+                report,
+            )
+            .context("couldn't rewrite files")?;
 
-    drop(root_sender);
+        for (input, _, _, bytes) in rewritten_files {
+            match input {
+                Input::File(path) => fs::write(&path, bytes)
+                    .with_context(|| format!("could not write {}", path.display()))?,
+                Input::Stdin(_) => bail!("I can't write a rewrite back to stdin"),
+            }
+        }
+    } else {
+        opts.searcher
+            .run(
+                rewrite,
+                |(input, source, rewritten, _)| {
+                    let name = input.to_string();
+                    let old = String::from_utf8_lossy(source.text());
+                    let new = String::from_utf8_lossy(&rewritten);
+
+                    write!(
+                        out,
+                        "{}",
+                        similar::TextDiff::from_lines(&old, &new)
+                            .unified_diff()
+                            .header(&name, &name)
+                    )
+                    .context("could not write diff")
+                },
+                report,
+            )
+            .context("couldn't rewrite files")?;
+    }
 
-    Ok(receiver.iter().collect())
+    Ok(())
 }
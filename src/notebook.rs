@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// The code in a Jupyter notebook. Markdown and raw cells are left out, but
+/// cells keep the index they had in the notebook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notebook {
+    language: Option<String>,
+    cells: Vec<Cell>,
+}
+
+/// A code cell. `index` starts at 1 and counts every cell in the notebook,
+/// not just code cells, so it matches what you'd count in Jupyter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    index: usize,
+    source: String,
+}
+
+impl Notebook {
+    /// Read a notebook from its JSON (nbformat 4.)
+    pub fn parse(source: &[u8]) -> Result<Notebook> {
+        let raw: RawNotebook = serde_json::from_slice(source)
+            .context("could not read a notebook. Only nbformat 4 notebooks are supported.")?;
+
+        let RawMetadata {
+            kernelspec,
+            language_info,
+        } = raw.metadata;
+        let language = kernelspec
+            .and_then(|kernelspec| kernelspec.language)
+            .or_else(|| language_info.and_then(|info| info.name))
+            .map(|language| language.to_lowercase());
+
+        let cells = raw
+            .cells
+            .into_iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.cell_type == "code")
+            .map(|(index, cell)| Cell {
+                index: index + 1,
+                source: match cell.source {
+                    RawSource::Text(text) => text,
+                    RawSource::Lines(lines) => lines.concat(),
+                },
+            })
+            .collect();
+
+        Ok(Notebook { language, cells })
+    }
+
+    /// The language of the notebook's kernel (like `python`), if it says.
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+}
+
+impl Cell {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The source of the cell with IPython's magics (`%time`, `!pip
+    /// install`) turned into comments, so they don't confuse the Python
+    /// parser. Positions stay the same. Cells that start with a cell magic
+    /// (like `%%bash`) aren't Python at all, so they come back as `None`.
+    pub fn python_source(&self) -> Option<String> {
+        if self.source.trim_start().starts_with("%%") {
+            return None;
+        }
+
+        let mut out = String::with_capacity(self.source.len());
+        for line in self.source.split_inclusive('\n') {
+            let indent = line.len() - line.trim_start().len();
+            match line[indent..].chars().next() {
+                Some('%') | Some('!') => {
+                    out.push_str(&line[..indent]);
+                    out.push('#');
+                    out.push_str(&line[indent + 1..]);
+                }
+                _ => out.push_str(line),
+            }
+        }
+
+        Some(out)
+    }
+}
+
+/// Whether we should read the file at `path` as a notebook.
+pub fn is_notebook(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("ipynb")
+}
+
+#[derive(Deserialize)]
+struct RawNotebook {
+    #[serde(default)]
+    metadata: RawMetadata,
+    cells: Vec<RawCell>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawMetadata {
+    kernelspec: Option<RawKernelspec>,
+    language_info: Option<RawLanguageInfo>,
+}
+
+#[derive(Deserialize)]
+struct RawKernelspec {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawLanguageInfo {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawCell {
+    cell_type: String,
+    #[serde(default)]
+    source: RawSource,
+}
+
+/// Notebooks can store source as one string or as a list of lines (with
+/// their newlines.)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSource {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Default for RawSource {
+    fn default() -> RawSource {
+        RawSource::Text(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_cells_keep_their_index() {
+        let notebook = Notebook::parse(
+            br##"{
+                "metadata": {"kernelspec": {"language": "python", "name": "python3"}},
+                "nbformat": 4,
+                "cells": [
+                    {"cell_type": "markdown", "source": ["# Hi"]},
+                    {"cell_type": "code", "source": ["import os\n", "os.getcwd()"]},
+                    {"cell_type": "code", "source": "print(1)"}
+                ]
+            }"##,
+        )
+        .unwrap();
+
+        assert_eq!(notebook.language(), Some("python"));
+        assert_eq!(
+            notebook
+                .cells()
+                .iter()
+                .map(|cell| (cell.index(), cell.source()))
+                .collect::<Vec<_>>(),
+            vec![(2, "import os\nos.getcwd()"), (3, "print(1)")]
+        );
+    }
+
+    #[test]
+    fn magics_become_comments() {
+        let cell = Cell {
+            index: 1,
+            source: String::from("%matplotlib inline\nif True:\n    !ls\n"),
+        };
+
+        assert_eq!(
+            cell.python_source(),
+            Some(String::from("#matplotlib inline\nif True:\n    #ls\n"))
+        );
+    }
+
+    #[test]
+    fn cell_magics_are_not_python() {
+        let cell = Cell {
+            index: 1,
+            source: String::from("%%bash\nls\n"),
+        };
+
+        assert_eq!(cell.python_source(), None);
+    }
+}
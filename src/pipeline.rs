@@ -0,0 +1,641 @@
+use crate::extractor::Extractor;
+use crate::extractor_chooser::{Choice, ExtractorChooser};
+use crate::file_error::{ErrorKind, FileError};
+use crate::input::Input;
+use crate::limits::{self, Limits};
+use crate::skip::{SkipReason, Skipped};
+use crate::stats::Stats;
+use anyhow::{bail, Context, Result};
+use crossbeam::channel;
+use ignore::DirEntry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use tree_sitter::Parser;
+
+/// Walks the filesystem, parses files, and hands results back, all at the same
+/// time. The walker feeds a pool of worker threads (each with their own
+/// `Parser`) and the workers send results back to the calling thread as soon as
+/// they're ready.
+#[derive(Debug)]
+pub struct Pipeline {
+    paths: Vec<PathBuf>,
+    opts: PipelineOpts,
+}
+
+/// How a `Pipeline` walks and searches. See `Pipeline::run` for what each of
+/// these does.
+#[derive(Debug, Clone)]
+pub struct PipelineOpts {
+    pub git_ignore: bool,
+    pub threads: usize,
+    pub sort: bool,
+    pub fail_fast: bool,
+    pub limits: Limits,
+    pub explain_skips: bool,
+}
+
+impl Default for PipelineOpts {
+    fn default() -> PipelineOpts {
+        PipelineOpts {
+            git_ignore: true,
+            threads: 1,
+            sort: false,
+            fail_fast: false,
+            limits: Limits::new(),
+            explain_skips: false,
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new(paths: Vec<PathBuf>, opts: PipelineOpts) -> Pipeline {
+        Pipeline {
+            paths,
+            opts: PipelineOpts {
+                threads: opts.threads.max(1),
+                ..opts
+            },
+        }
+    }
+
+    /// Run `work` on every file that `chooser` has extractors for (with all of
+    /// them), and call `emit` with each result that isn't `None`.
+    ///
+    /// Errors from `work` or from walking the filesystem go to `report`, and
+    /// the run keeps going. When failing fast, we stop everything at the first
+    /// one and return it instead. Errors from `emit` or `report` always stop
+    /// the run.
+    ///
+    /// Files that take too long (see `Limits`) go to `report` too, but they're
+    /// counted as skipped instead of as errors, and they never stop the run.
+    /// Once the deadline passes, we stop walking and starting new files.
+    ///
+    /// When explaining skips, each file the chooser skips goes to `report`
+    /// as a `Skipped` error, in the same order as results. Those aren't
+    /// counted as errors either.
+    ///
+    /// When sorting, files are walked in path order and results are held back
+    /// until everything before them has been emitted. That keeps the output
+    /// deterministic without waiting for the whole search to finish.
+    pub fn run<'extractor, T, W, E, R>(
+        &self,
+        chooser: &ExtractorChooser<'extractor>,
+        work: W,
+        mut emit: E,
+        mut report: R,
+    ) -> Result<Stats>
+    where
+        T: Send,
+        W: Fn(&mut Parser, &Input, &[&'extractor Extractor]) -> Result<Option<T>> + Sync,
+        E: FnMut(T) -> Result<()>,
+        R: FnMut(anyhow::Error) -> Result<()>,
+    {
+        let mut builder = self.walk_builder()?;
+        let tally = &Tally::default();
+        let mut errors = 0;
+        let mut timeouts = 0;
+
+        thread::scope(|scope| {
+            let (job_sender, job_receiver) = channel::bounded(self.opts.threads * 16);
+            let (result_sender, result_receiver) = channel::bounded(self.opts.threads * 16);
+
+            // when sorting, results that finish early wait for the ones
+            // before them. So they can't pile up behind one slow file, the
+            // walker takes a slot here for every job it starts, and we only
+            // give the slot back once the job's result has gone out.
+            let (slot_sender, slot_receiver) = channel::bounded(self.opts.threads * 16);
+
+            scope.spawn(move || {
+                if self.opts.sort {
+                    let walk = builder.sort_by_file_name(|a, b| a.cmp(b)).build();
+
+                    let jobs = walk
+                        .take_while(|_| !tally.stop_at(&self.opts.limits))
+                        .filter_map(|entry_result| {
+                            tally.job(chooser, entry_result, self.opts.explain_skips)
+                        })
+                        .enumerate();
+
+                    for (index, job) in jobs {
+                        if slot_sender.send(()).is_err() || job_sender.send((index, job)).is_err() {
+                            break;
+                        }
+                    }
+                } else {
+                    builder.threads(self.opts.threads).build_parallel().run(|| {
+                        let sender = job_sender.clone();
+                        Box::new(move |entry_result| {
+                            if tally.stop_at(&self.opts.limits) {
+                                return ignore::WalkState::Quit;
+                            }
+
+                            match tally.job(chooser, entry_result, self.opts.explain_skips) {
+                                Some(job) => match sender.send((0, job)) {
+                                    Ok(()) => ignore::WalkState::Continue,
+                                    Err(_) => ignore::WalkState::Quit,
+                                },
+                                None => ignore::WalkState::Continue,
+                            }
+                        })
+                    });
+                }
+            });
+
+            for _ in 0..self.opts.threads {
+                let receiver = job_receiver.clone();
+                let sender = result_sender.clone();
+                let work = &work;
+
+                scope.spawn(move || {
+                    let mut parser = Parser::new();
+
+                    for (index, job) in receiver {
+                        // jobs still in the queue at the deadline are
+                        // dropped, along with their results.
+                        if tally.stop_at(&self.opts.limits) {
+                            break;
+                        }
+
+                        let result = job
+                            .and_then(|(input, extractors)| work(&mut parser, &input, &extractors));
+
+                        // if nobody is listening anymore (for example because
+                        // an earlier error stopped everything) there's no point
+                        // in continuing.
+                        if sender.send((index, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+
+            // the workers have their own copies of these. Once they're done,
+            // the channels close and our loop below ends.
+            drop(job_receiver);
+            drop(result_sender);
+
+            let mut handle = |result: Result<Option<T>>| match result {
+                Ok(Some(item)) => emit(item),
+                Ok(None) => Ok(()),
+                Err(error) if error.is::<Skipped>() => report(error),
+                Err(error) if limits::timed_out(&error).is_some() => {
+                    timeouts += 1;
+                    report(error)
+                }
+                Err(error) if self.opts.fail_fast => Err(error),
+                Err(error) => {
+                    errors += 1;
+                    report(error)
+                }
+            };
+
+            if self.opts.sort {
+                let mut next = 0;
+                let mut waiting = HashMap::new();
+
+                for (index, result) in result_receiver {
+                    waiting.insert(index, result);
+
+                    while let Some(result) = waiting.remove(&next) {
+                        handle(result)?;
+                        next += 1;
+                        let _ = slot_receiver.try_recv();
+                    }
+                }
+
+                // if the deadline stopped the workers, some results never
+                // came back. Whatever's left still goes out in order.
+                let mut rest: Vec<_> = waiting.into_iter().collect();
+                rest.sort_by_key(|(index, _)| *index);
+                for (_, result) in rest {
+                    handle(result)?;
+                }
+            } else {
+                for (_, result) in result_receiver {
+                    handle(result)?;
+                }
+            }
+
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+        Ok(Stats {
+            files_skipped_timeout: timeouts,
+            errors,
+            ..tally.stats()
+        })
+    }
+
+    fn walk_builder(&self) -> Result<ignore::WalkBuilder> {
+        let mut paths = self.paths.clone();
+        if self.opts.sort {
+            paths.sort();
+        }
+
+        let mut builder = match paths.split_first() {
+            Some((first, rest)) => {
+                let mut builder = ignore::WalkBuilder::new(first);
+                for path in rest {
+                    builder.add(path);
+                }
+
+                builder
+            }
+            None => bail!("I need at least one file or directory to walk!"),
+        };
+
+        builder
+            .git_ignore(self.opts.git_ignore)
+            .git_exclude(self.opts.git_ignore)
+            .git_global(self.opts.git_ignore);
+
+        Ok(builder)
+    }
+}
+
+/// A file to search (with the extractors to search it with), or a problem
+/// the walker ran into.
+type Job<'extractor> = Result<(Input, Vec<&'extractor Extractor>)>;
+
+/// Counts of what the walker chose to do with each file. The parallel walker
+/// chooses on several threads at once, so these are atomic.
+#[derive(Default)]
+struct Tally {
+    searched: AtomicUsize,
+    generated: AtomicUsize,
+    binary: AtomicUsize,
+    minified: AtomicUsize,
+    too_big: AtomicUsize,
+    unmodified: AtomicUsize,
+    deadline_passed: AtomicBool,
+}
+
+impl Tally {
+    /// What to do with something the walker found: search it with some
+    /// extractors, report an error (or, if we're explaining them, a skip),
+    /// or nothing at all.
+    fn job<'extractor>(
+        &self,
+        chooser: &ExtractorChooser<'extractor>,
+        entry_result: Result<DirEntry, ignore::Error>,
+        explain_skips: bool,
+    ) -> Option<Job<'extractor>> {
+        let entry = match entry_result {
+            Ok(entry) => entry,
+            Err(error) => {
+                let context = FileError::new(ErrorKind::Walk, walk_error_path(&error));
+                return Some(Err(error).context(context));
+            }
+        };
+
+        match chooser.choose(&entry) {
+            Choice::Search(extractors) => {
+                if !extractors
+                    .iter()
+                    .all(|extractor| extractor.injections_only())
+                {
+                    self.searched.fetch_add(1, Ordering::Relaxed);
+                }
+                Some(Ok((Input::File(entry.into_path()), extractors)))
+            }
+            Choice::Skipped(reason) => {
+                self.skipped(reason).fetch_add(1, Ordering::Relaxed);
+                if explain_skips {
+                    Some(Err(Skipped::new(entry.into_path(), reason).into()))
+                } else {
+                    None
+                }
+            }
+            Choice::Skip => None,
+        }
+    }
+
+    fn skipped(&self, reason: SkipReason) -> &AtomicUsize {
+        match reason {
+            SkipReason::Generated => &self.generated,
+            SkipReason::Binary => &self.binary,
+            SkipReason::Minified => &self.minified,
+            SkipReason::TooBig { .. } => &self.too_big,
+            SkipReason::Unmodified => &self.unmodified,
+        }
+    }
+
+    /// Whether to stop because the deadline has passed. We remember if we
+    /// did, so the stats can say the search was cut short.
+    fn stop_at(&self, limits: &Limits) -> bool {
+        let passed = limits.deadline_passed();
+        if passed {
+            self.deadline_passed.store(true, Ordering::Relaxed);
+        }
+
+        passed
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            files_searched: self.searched.load(Ordering::Relaxed),
+            files_skipped_generated: self.generated.load(Ordering::Relaxed),
+            files_skipped_binary: self.binary.load(Ordering::Relaxed),
+            files_skipped_minified: self.minified.load(Ordering::Relaxed),
+            files_skipped_too_big: self.too_big.load(Ordering::Relaxed),
+            files_skipped_unmodified: self.unmodified.load(Ordering::Relaxed),
+            deadline_passed: self.deadline_passed.load(Ordering::Relaxed),
+            ..Stats::default()
+        }
+    }
+}
+
+/// The path a walker error is about, if it says.
+fn walk_error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(
+    not(any(
+        all(feature = "c", feature = "cpp"),
+        feature = "javascript",
+        feature = "python"
+    )),
+    allow(dead_code, unused_imports)
+)]
+mod tests {
+    use super::*;
+    use crate::file_types::FileTypes;
+    use crate::language::Language;
+
+    fn extractors(languages: &[Language], query: &str) -> Vec<Extractor> {
+        languages
+            .iter()
+            .map(|lang| Extractor::new(lang.clone(), lang.parse_query(query).unwrap()))
+            .collect()
+    }
+
+    fn chooser_for(extractors: &[Extractor]) -> ExtractorChooser<'_> {
+        ExtractorChooser::from_extractors(extractors, &FileTypes::new()).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn sorted_runs_emit_results_from_workers() {
+        let extractors = extractors(&[Language::JavaScript], "(string_fragment)@string");
+        let chooser = chooser_for(&extractors);
+
+        let mut paths = Vec::new();
+        Pipeline::new(
+            vec![PathBuf::from("tests/cmd")],
+            PipelineOpts {
+                threads: 4,
+                sort: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, input, _| Ok(input.path().map(|path| path.to_owned())),
+            |path| {
+                paths.push(path);
+                Ok(())
+            },
+            Err,
+        )
+        .unwrap();
+
+        assert_eq!(paths, vec![PathBuf::from("tests/cmd/hello-world.js")]);
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn generated_files_are_counted_but_not_searched() {
+        let extractors = extractors(&[Language::Python], "(identifier)@id");
+        let chooser = chooser_for(&extractors).skip_generated(true);
+
+        let stats = Pipeline::new(
+            vec![PathBuf::from("tests/cmd/generated")],
+            PipelineOpts {
+                sort: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(&chooser, |_, _, _| Ok(Some(())), |_| Ok(()), Err)
+        .unwrap();
+
+        assert_eq!(stats.files_searched(), 1);
+        assert_eq!(stats.files_skipped_generated(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "python")]
+    fn skips_are_reported_in_order_when_explaining() {
+        let extractors = extractors(&[Language::Python], "(identifier)@id");
+        let chooser = chooser_for(&extractors).skip_generated(true);
+
+        let mut skipped = Vec::new();
+        let stats = Pipeline::new(
+            vec![PathBuf::from("tests/cmd/generated")],
+            PipelineOpts {
+                threads: 2,
+                sort: true,
+                explain_skips: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, _| Ok(Some(())),
+            |_| Ok(()),
+            |error| {
+                let found = error.downcast::<Skipped>()?;
+                skipped.push((found.path().to_owned(), found.reason()));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(stats.errors(), 0);
+        assert_eq!(
+            skipped,
+            vec![
+                (
+                    PathBuf::from("tests/cmd/generated/api_pb2.py"),
+                    SkipReason::Generated
+                ),
+                (
+                    PathBuf::from("tests/cmd/generated/vendor/six.py"),
+                    SkipReason::Generated
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "c", feature = "cpp"))]
+    fn files_get_every_extractor_that_claims_them() {
+        let extractors = extractors(&[Language::Cpp, Language::C], "(identifier)@id");
+        let chooser = chooser_for(&extractors);
+
+        let mut languages = Vec::new();
+        Pipeline::new(
+            vec![PathBuf::from("tests/cmd/header.h")],
+            PipelineOpts {
+                sort: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, extractors| {
+                Ok(Some(
+                    extractors
+                        .iter()
+                        .map(|extractor| extractor.language().to_string())
+                        .collect::<Vec<_>>(),
+                ))
+            },
+            |found| {
+                languages.push(found);
+                Ok(())
+            },
+            Err,
+        )
+        .unwrap();
+
+        assert_eq!(
+            languages,
+            vec![vec![String::from("c"), String::from("cpp")]]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn errors_are_reported_and_the_run_keeps_going() {
+        let extractors = extractors(&[Language::JavaScript], "(string_fragment)@string");
+        let chooser = chooser_for(&extractors);
+
+        let mut reported = Vec::new();
+        let stats = Pipeline::new(
+            vec![
+                PathBuf::from("tests/cmd/missing"),
+                PathBuf::from("tests/cmd/hello-world.js"),
+            ],
+            PipelineOpts {
+                sort: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, _| -> Result<Option<()>> { bail!("nope") },
+            |_| Ok(()),
+            |error| {
+                let kind = error
+                    .downcast_ref::<FileError>()
+                    .map(|found| (found.kind(), found.path().map(|path| path.to_owned())));
+                reported.push((kind, format!("{:#}", error)));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        // paths are sorted, so the file comes before the missing directory
+        assert_eq!(stats.errors(), 2);
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0], (None, String::from("nope")));
+        assert_eq!(
+            reported[1].0,
+            Some((ErrorKind::Walk, Some(PathBuf::from("tests/cmd/missing"))))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn errors_stop_the_run_when_failing_fast() {
+        let extractors = extractors(&[Language::JavaScript], "(string_fragment)@string");
+        let chooser = chooser_for(&extractors);
+
+        let result = Pipeline::new(
+            vec![PathBuf::from("tests/cmd")],
+            PipelineOpts {
+                threads: 4,
+                fail_fast: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, _| -> Result<Option<()>> { bail!("nope") },
+            |_| Ok(()),
+            |_| Ok(()),
+        );
+
+        assert_eq!(result.unwrap_err().to_string(), "nope");
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn timeouts_are_skips_even_when_failing_fast() {
+        let extractors = extractors(&[Language::JavaScript], "(string_fragment)@string");
+        let chooser = chooser_for(&extractors);
+
+        let stats = Pipeline::new(
+            vec![PathBuf::from("tests/cmd/hello-world.js")],
+            PipelineOpts {
+                sort: true,
+                fail_fast: true,
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, _| -> Result<Option<()>> {
+                Err(limits::TimedOut::Parse(std::time::Duration::ZERO).into())
+            },
+            |_| Ok(()),
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(stats.files_skipped_timeout(), 1);
+        assert_eq!(stats.errors(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn nothing_is_searched_after_the_deadline() {
+        let extractors = extractors(&[Language::JavaScript], "(string_fragment)@string");
+        let chooser = chooser_for(&extractors);
+
+        let mut emitted = 0;
+        let stats = Pipeline::new(
+            vec![PathBuf::from("tests/cmd")],
+            PipelineOpts {
+                threads: 2,
+                sort: true,
+                limits: Limits::new().deadline(std::time::Instant::now()),
+                ..PipelineOpts::default()
+            },
+        )
+        .run(
+            &chooser,
+            |_, _, _| Ok(Some(())),
+            |_| {
+                emitted += 1;
+                Ok(())
+            },
+            Err,
+        )
+        .unwrap();
+
+        assert_eq!(emitted, 0);
+        assert!(stats.deadline_passed());
+    }
+}
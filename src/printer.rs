@@ -0,0 +1,389 @@
+use crate::extractor::{ContextLine, ExtractedFile};
+use anyhow::{bail, Result};
+use std::fmt::{self, Display, Write};
+use std::path::{Path, PathBuf};
+
+// These match ripgrep's defaults, so hopefully they'll feel familiar.
+const PATH_STYLE: &str = "35";
+const POSITION_STYLE: &str = "32";
+const NAME_STYLE: &str = "36";
+const TEXT_STYLE: &str = "1;31";
+
+/// Prints `ExtractedFile`s in the `lines` format, optionally with colors,
+/// matches grouped under a heading for each file, and terminal hyperlinks.
+/// With none of those turned on, this is exactly what `ExtractedFile`'s
+/// `Display` impl gives you.
+#[derive(Debug, Default)]
+pub struct LinesPrinter {
+    color: bool,
+    heading: bool,
+    hyperlinks: Option<HyperlinkFormat>,
+}
+
+impl LinesPrinter {
+    /// Hyperlinks are only printed when `color` is on, since they're escape
+    /// codes too and we want piped output to stay plain.
+    pub fn new(color: bool, heading: bool, hyperlinks: Option<HyperlinkFormat>) -> LinesPrinter {
+        LinesPrinter {
+            color,
+            heading,
+            hyperlinks,
+        }
+    }
+
+    pub fn display<'a, 'query>(
+        &'a self,
+        extracted_file: &'a ExtractedFile<'query>,
+    ) -> PrintedFile<'a, 'query> {
+        PrintedFile {
+            printer: self,
+            extracted_file,
+        }
+    }
+
+    fn write(&self, f: &mut impl Write, extracted_file: &ExtractedFile) -> fmt::Result {
+        // TODO: is there a better way to do this unwrapping? This implementation
+        // turns non-UTF-8 paths into "NON-UTF8 FILENAME". I don't know exactly
+        // what circumstances that could happen in... maybe we should just wait
+        // for bug reports?
+        let filename = extracted_file
+            .file()
+            .map(|f| f.to_str().unwrap_or("NON-UTF8 FILENAME"))
+            .unwrap_or("NO FILE");
+
+        // we need an absolute path for links, but there's no point in asking
+        // the filesystem for one if we're not going to use it.
+        let link_path = match (&self.hyperlinks, self.color) {
+            (Some(_), true) => extracted_file.file().and_then(absolute),
+            _ => None,
+        };
+        let link = |row: usize, column: usize| match (&self.hyperlinks, &link_path) {
+            (Some(format), Some(path)) => Some(format.render(path, row, column)),
+            _ => None,
+        };
+
+        if self.heading {
+            self.write_link(f, link(1, 1), |f| self.paint(f, PATH_STYLE, filename))?;
+            writeln!(f)?;
+        }
+
+        // Context lines work like grep's: they're printed as `file-row-text`,
+        // each line is printed at most once even if several matches want it,
+        // and non-adjacent groups are separated by `--`.
+        let mut printed_until: Option<usize> = None;
+        let mut pending_after: Vec<&ContextLine> = Vec::new();
+        let mut current_cell = None;
+
+        for extraction in extracted_file.matches() {
+            let start_row = extraction.start().row + 1;
+            let start_column = extraction.start().column + 1;
+
+            // rows start over in each notebook cell, so context from the last
+            // cell has to be finished before we start on the next one.
+            if extraction.cell() != current_cell {
+                pending_after.sort_by_key(|line| line.row());
+                for line in pending_after.drain(..) {
+                    self.write_context_line(f, filename, current_cell, line, &mut printed_until)?;
+                }
+
+                if printed_until.take().is_some() {
+                    writeln!(f, "--")?;
+                }
+                current_cell = extraction.cell();
+            }
+
+            if let Some(context) = extraction.context() {
+                pending_after.extend(context.iter().filter(|line| line.row() < start_row));
+                pending_after.sort_by_key(|line| line.row());
+
+                let (before, after): (Vec<&ContextLine>, Vec<&ContextLine>) = pending_after
+                    .into_iter()
+                    .partition(|line| line.row() < start_row);
+
+                for line in before {
+                    self.write_context_line(f, filename, current_cell, line, &mut printed_until)?;
+                }
+                pending_after = after;
+
+                if let Some(previous) = printed_until {
+                    if start_row > previous + 1 {
+                        writeln!(f, "--")?;
+                    }
+                }
+            }
+
+            // positions in cells aren't positions in the file, so there's
+            // nothing to link to.
+            let url = match extraction.cell() {
+                Some(_) => None,
+                None => link(start_row, start_column),
+            };
+
+            self.write_link(f, url, |f| {
+                if !self.heading {
+                    self.paint(f, PATH_STYLE, filename)?;
+                    f.write_char(':')?;
+                }
+                if let Some(cell) = extraction.cell() {
+                    self.paint(f, POSITION_STYLE, &format!("cell {}", cell))?;
+                    f.write_char(':')?;
+                }
+                self.paint(f, POSITION_STYLE, &start_row.to_string())?;
+                f.write_char(':')?;
+                self.paint(f, POSITION_STYLE, &start_column.to_string())
+            })?;
+            f.write_char(':')?;
+            self.paint(f, NAME_STYLE, extraction.name())?;
+            f.write_char(':')?;
+            self.paint(f, TEXT_STYLE, extraction.text())?;
+            writeln!(f)?;
+
+            if let Some(context) = extraction.context() {
+                let end_row = extraction.end().row + 1;
+                printed_until = Some(printed_until.unwrap_or(0).max(end_row));
+                pending_after.extend(context.iter().filter(|line| line.row() > end_row));
+            }
+        }
+
+        pending_after.sort_by_key(|line| line.row());
+        for line in pending_after {
+            self.write_context_line(f, filename, current_cell, line, &mut printed_until)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_context_line(
+        &self,
+        f: &mut impl Write,
+        filename: &str,
+        cell: Option<usize>,
+        line: &ContextLine,
+        printed_until: &mut Option<usize>,
+    ) -> fmt::Result {
+        if let Some(previous) = *printed_until {
+            if line.row() <= previous {
+                return Ok(());
+            }
+
+            if line.row() > previous + 1 {
+                writeln!(f, "--")?;
+            }
+        }
+
+        *printed_until = Some(line.row());
+
+        if !self.heading {
+            self.paint(f, PATH_STYLE, filename)?;
+            f.write_char('-')?;
+        }
+        if let Some(cell) = cell {
+            self.paint(f, POSITION_STYLE, &format!("cell {}", cell))?;
+            f.write_char('-')?;
+        }
+        self.paint(f, POSITION_STYLE, &line.row().to_string())?;
+        writeln!(f, "-{}", line.text())
+    }
+
+    fn paint(&self, f: &mut impl Write, style: &str, text: &str) -> fmt::Result {
+        if self.color {
+            write!(f, "\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            f.write_str(text)
+        }
+    }
+
+    fn write_link<F, W>(&self, f: &mut W, url: Option<String>, inner: F) -> fmt::Result
+    where
+        W: Write,
+        F: FnOnce(&mut W) -> fmt::Result,
+    {
+        match url {
+            Some(url) => {
+                // OSC 8. See https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
+                write!(f, "\x1b]8;;{}\x1b\\", url)?;
+                inner(f)?;
+                write!(f, "\x1b]8;;\x1b\\")
+            }
+            None => inner(f),
+        }
+    }
+}
+
+/// An `ExtractedFile` as printed by a `LinesPrinter`. Get one with
+/// `LinesPrinter::display`.
+pub struct PrintedFile<'a, 'query> {
+    printer: &'a LinesPrinter,
+    extracted_file: &'a ExtractedFile<'query>,
+}
+
+impl Display for PrintedFile<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.printer.write(f, self.extracted_file)
+    }
+}
+
+/// A template for the URLs in terminal hyperlinks. `{path}` is replaced with
+/// the absolute path of the file, and `{line}` and `{column}` with the
+/// position of the match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperlinkFormat {
+    template: String,
+}
+
+impl HyperlinkFormat {
+    /// Accepts either a template or the name of a preset: `default` (a plain
+    /// `file://` URL) or `vscode`.
+    pub fn parse(raw: &str) -> Result<HyperlinkFormat> {
+        let template = match raw {
+            "default" => "file://{path}",
+            "vscode" => "vscode://file{path}:{line}:{column}",
+            other if other.contains("{path}") => other,
+            other => bail!(
+                "a hyperlink format needs to include {{path}}, or be one of `default` or `vscode`, but I got `{}`",
+                other
+            ),
+        };
+
+        Ok(HyperlinkFormat {
+            template: template.to_string(),
+        })
+    }
+
+    pub fn render(&self, path: &Path, line: usize, column: usize) -> String {
+        self.template
+            .replace("{path}", &encode_path(path))
+            .replace("{line}", &line.to_string())
+            .replace("{column}", &column.to_string())
+    }
+}
+
+fn absolute(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok()
+}
+
+/// Percent-encode a path for use in a URL, leaving slashes alone.
+fn encode_path(path: &Path) -> String {
+    let raw = path.to_string_lossy();
+    let mut out = String::with_capacity(raw.len());
+
+    // Windows paths (like `C:\foo`) need a leading slash and forward slashes
+    // to be valid in a URL.
+    if !raw.starts_with('/') {
+        out.push('/');
+    }
+
+    for byte in raw.bytes() {
+        match byte {
+            b'\\' => out.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "javascript")]
+    use crate::extractor::Extractor;
+    #[cfg(feature = "javascript")]
+    use crate::language::Language;
+    #[cfg(feature = "javascript")]
+    use tree_sitter::Parser;
+
+    #[cfg(feature = "javascript")]
+    fn print(printer: &LinesPrinter) -> String {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let extractor = Extractor::new(lang, query);
+
+        let extracted = extractor
+            .extract_from_text(None, b"a();\nb();\n", &mut Parser::new())
+            // From Result<Option<ExtractedFile>>
+            .unwrap()
+            // From Option<ExtractedFile>
+            .unwrap();
+
+        printer.display(&extracted).to_string()
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn plain_output_matches_display() {
+        assert_eq!(
+            print(&LinesPrinter::default()),
+            "NO FILE:1:1:call:a()\nNO FILE:2:1:call:b()\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn headings_group_matches_under_the_file() {
+        assert_eq!(
+            print(&LinesPrinter::new(false, true, None)),
+            "NO FILE\n1:1:call:a()\n2:1:call:b()\n"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn colors_wrap_each_part() {
+        assert_eq!(
+            print(&LinesPrinter::new(true, true, None)).lines().nth(1),
+            Some("\x1b[32m1\x1b[0m:\x1b[32m1\x1b[0m:\x1b[36mcall\x1b[0m:\x1b[1;31ma()\x1b[0m")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn notebook_cells_have_their_own_rows() {
+        let lang = Language::JavaScript;
+        let query = lang.parse_query("(call_expression)@call").unwrap();
+        let extractor = Extractor::new(lang, query).with_context(1, 1);
+        let mut parser = Parser::new();
+
+        let mut extracted = extractor
+            .extract_from_text(None, b"// 1\na();\n", &mut parser)
+            .unwrap()
+            .unwrap();
+        extracted.set_cell(2);
+
+        let mut next = extractor
+            .extract_from_text(None, b"b();\n// 2\n", &mut parser)
+            .unwrap()
+            .unwrap();
+        next.set_cell(3);
+        extracted.merge(next);
+
+        assert_eq!(
+            LinesPrinter::default().display(&extracted).to_string(),
+            "NO FILE-cell 2-1-// 1\n\
+             NO FILE:cell 2:2:1:call:a()\n\
+             --\n\
+             NO FILE:cell 3:1:1:call:b()\n\
+             NO FILE-cell 3-2-// 2\n"
+        );
+    }
+
+    #[test]
+    fn hyperlink_presets() {
+        let path = Path::new("/src/some file.rs");
+
+        assert_eq!(
+            HyperlinkFormat::parse("default")
+                .unwrap()
+                .render(path, 1, 2),
+            "file:///src/some%20file.rs"
+        );
+        assert_eq!(
+            HyperlinkFormat::parse("vscode").unwrap().render(path, 1, 2),
+            "vscode://file/src/some%20file.rs:1:2"
+        );
+        assert!(HyperlinkFormat::parse("nope").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::language::Language;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tree_sitter::Query;
+
+/// A query, and the file it came from if it didn't come from the command
+/// line. We keep track of that so that errors can point at a line in the
+/// file instead of a position in the string we parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryText {
+    language: Language,
+    text: String,
+    origin: Option<Origin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Origin {
+    path: PathBuf,
+    // how many lines of the file come before the query text
+    first_row: usize,
+}
+
+impl QueryText {
+    pub fn inline(language: Language, text: String) -> QueryText {
+        QueryText {
+            language,
+            text,
+            origin: None,
+        }
+    }
+
+    /// Read a query for a single language from a file.
+    pub fn read(language: Language, path: &Path) -> Result<QueryText> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read query file {}", path.display()))?;
+
+        Ok(QueryText::from_file(language, path, text, 0))
+    }
+
+    /// Read a file with queries for several languages. Each language's
+    /// queries start with a comment like `; language: javascript` on its own
+    /// line and go until the next one (or the end of the file.) Comments and
+    /// blank lines are fine before the first section, but queries aren't,
+    /// since we wouldn't know what language they were for.
+    pub fn read_sections(path: &Path) -> Result<Vec<QueryText>> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read query file {}", path.display()))?;
+
+        QueryText::parse_sections(path, &text)
+    }
+
+    fn parse_sections(path: &Path, text: &str) -> Result<Vec<QueryText>> {
+        let mut out = Vec::new();
+        let mut current: Option<(Language, usize, String)> = None;
+
+        for (row, line) in text.split_inclusive('\n').enumerate() {
+            if let Some(raw_lang) = section_header(line) {
+                let language = Language::from_str(raw_lang).with_context(|| {
+                    format!(
+                        "could not parse the language in {}:{}",
+                        path.display(),
+                        row + 1
+                    )
+                })?;
+
+                if let Some((language, first_row, text)) = current.take() {
+                    out.push(QueryText::from_file(language, path, text, first_row));
+                }
+                current = Some((language, row + 1, String::new()));
+            } else if let Some((_, _, text)) = current.as_mut() {
+                text.push_str(line);
+            } else {
+                let trimmed = line.trim();
+                if !trimmed.is_empty() && !trimmed.starts_with(';') {
+                    bail!(
+                        "found a query at {}:{} before any `; language: NAME` line, so I don't know what language it's for",
+                        path.display(),
+                        row + 1
+                    )
+                }
+            }
+        }
+
+        if let Some((language, first_row, text)) = current {
+            out.push(QueryText::from_file(language, path, text, first_row));
+        }
+
+        if out.is_empty() {
+            bail!(
+                "{} doesn't have any `; language: NAME` lines, so I couldn't find any queries in it",
+                path.display()
+            )
+        }
+
+        Ok(out)
+    }
+
+    fn from_file(language: Language, path: &Path, mut text: String, first_row: usize) -> QueryText {
+        // queries get glued together, so a comment on the last line without
+        // a newline would swallow whatever comes after it.
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+
+        QueryText {
+            language,
+            text,
+            origin: Some(Origin {
+                path: path.to_owned(),
+                first_row,
+            }),
+        }
+    }
+
+    pub fn language(&self) -> &Language {
+        &self.language
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn parse(&self) -> Result<Query> {
+        match &self.origin {
+            None => self
+                .language
+                .parse_query(&self.text)
+                .context("could not parse query"),
+            Some(origin) => Query::new(&self.language.language(), &self.text)
+                .map_err(|mut err| {
+                    err.row += origin.first_row;
+                    anyhow!("{}", err)
+                })
+                .with_context(|| format!("could not parse query in {}", origin.path.display())),
+        }
+    }
+}
+
+fn section_header(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix(';')?
+        .trim_start_matches(';')
+        .trim_start()
+        .strip_prefix("language:")
+        .map(|lang| lang.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(feature = "elm", feature = "javascript"))]
+    fn sections_are_split_by_language() {
+        let queries = QueryText::parse_sections(
+            Path::new("queries.scm"),
+            ";; shared queries\n\n; language: javascript\n(call_expression)@call\n;; language: elm\n(import_clause)\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            queries
+                .iter()
+                .map(|query| (query.language().clone(), query.text()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Language::JavaScript, "(call_expression)@call\n"),
+                (Language::Elm, "(import_clause)\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn queries_need_a_section() {
+        assert_eq!(
+            QueryText::parse_sections(Path::new("queries.scm"), "(identifier)\n")
+                .unwrap_err()
+                .to_string(),
+            "found a query at queries.scm:1 before any `; language: NAME` line, so I don't know what language it's for"
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "elm", feature = "javascript"))]
+    fn errors_point_at_the_line_in_the_file() {
+        let queries = QueryText::parse_sections(
+            Path::new("queries.scm"),
+            "; language: elm\n(import_clause)\n\n; language: javascript\n(identifier)\n(node_that_doesnt_exist)\n",
+        )
+        .unwrap();
+
+        assert!(queries[0].parse().is_ok());
+        assert_eq!(
+            format!("{:#}", queries[1].parse().unwrap_err()),
+            "could not parse query in queries.scm: Query error at 6:2. Invalid node type node_that_doesnt_exist"
+        );
+    }
+}
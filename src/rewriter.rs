@@ -0,0 +1,262 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::Point;
+
+#[derive(Debug)]
+pub struct Rewrite {
+    capture: String,
+    template: Template,
+}
+
+impl Rewrite {
+    pub fn new(capture: String, raw_template: &str) -> Result<Rewrite> {
+        Ok(Rewrite {
+            capture,
+            template: Template::parse(raw_template).context("could not parse rewrite template")?,
+        })
+    }
+
+    pub fn capture(&self) -> &str {
+        &self.capture
+    }
+
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Capture(String),
+}
+
+/// A replacement template like `{fn}({args}, ctx)`. Names in braces refer to
+/// captures in the same match, and `{{` or `}}` produce literal braces.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(raw: &str) -> Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = raw.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                bail!("unclosed `{{` in template. Use `{{{{` for a literal brace.")
+                            }
+                        }
+                    }
+
+                    if name.is_empty() {
+                        bail!("found an empty capture name (`{{}}`) in template")
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Capture(name));
+                }
+                '}' => bail!("unmatched `}}` in template. Use `}}}}` for a literal brace."),
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    pub fn capture_names(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Capture(name) => Some(name.as_str()),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Fill in the template. Captures that did not participate in the match
+    /// (for example because they were optional) render as empty strings.
+    ///
+    /// This works on bytes instead of strings so that captured text that
+    /// isn't valid UTF-8 goes back in the file exactly as it came out.
+    pub fn render(&self, captures: &HashMap<&str, &[u8]>) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.extend_from_slice(literal.as_bytes()),
+                Segment::Capture(name) => {
+                    out.extend_from_slice(captures.get(name.as_str()).copied().unwrap_or(b""))
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub bytes: Range<usize>,
+    pub start: Point,
+    pub replacement: Vec<u8>,
+}
+
+/// Apply edits to the source, returning the rewritten text. Edits that touch
+/// the same bytes can't be applied together without mangling the file, so we
+/// return an error pointing at both instead of guessing.
+pub fn apply_edits(path: Option<&Path>, source: &[u8], mut edits: Vec<Edit>) -> Result<Vec<u8>> {
+    edits.sort_by_key(|edit| (edit.bytes.start, edit.bytes.end));
+    // multiple patterns (or multiple matches of the same pattern) can produce
+    // the exact same edit. Those aren't conflicts, so we just apply them once.
+    edits.dedup();
+
+    let filename = path
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| String::from("NO FILE"));
+
+    let mut out = Vec::with_capacity(source.len());
+    let mut position = 0;
+    let mut previous: Option<&Edit> = None;
+
+    for edit in &edits {
+        if let Some(previous) = previous {
+            // two different edits that start at the same place conflict even
+            // if they're both insertions, since there's no right order to put
+            // them in.
+            if edit.bytes.start < previous.bytes.end || edit.bytes.start == previous.bytes.start {
+                bail!(
+                    "overlapping edits in {} at {}:{} and {}:{}",
+                    filename,
+                    previous.start.row + 1,
+                    previous.start.column + 1,
+                    edit.start.row + 1,
+                    edit.start.column + 1,
+                )
+            }
+        }
+
+        out.extend_from_slice(&source[position..edit.bytes.start]);
+        out.extend_from_slice(&edit.replacement);
+        position = edit.bytes.end;
+        previous = Some(edit);
+    }
+
+    out.extend_from_slice(&source[position..]);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(start: usize, end: usize, replacement: &str) -> Edit {
+        Edit {
+            bytes: start..end,
+            start: Point {
+                row: 0,
+                column: start,
+            },
+            replacement: replacement.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn template_renders_captures() {
+        let template = Template::parse("{fn}({args}, ctx)").unwrap();
+        let mut captures = HashMap::new();
+        captures.insert("fn", &b"foo"[..]);
+        captures.insert("args", b"a, b");
+
+        assert_eq!(template.render(&captures), b"foo(a, b, ctx)");
+    }
+
+    #[test]
+    fn template_escapes_braces() {
+        let template = Template::parse("{{{name}}}").unwrap();
+        let mut captures = HashMap::new();
+        captures.insert("name", &b"x"[..]);
+
+        assert_eq!(template.render(&captures), b"{x}");
+    }
+
+    #[test]
+    fn template_keeps_bytes_that_are_not_utf8() {
+        let template = Template::parse("[{name}]").unwrap();
+        let mut captures = HashMap::new();
+        captures.insert("name", &b"caf\xE9"[..]);
+
+        assert_eq!(template.render(&captures), b"[caf\xE9]");
+    }
+
+    #[test]
+    fn template_rejects_unclosed_braces() {
+        assert!(Template::parse("{fn").is_err());
+        assert!(Template::parse("fn}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+
+    #[test]
+    fn edits_are_applied_in_order() {
+        let out = apply_edits(
+            None,
+            b"one two three",
+            vec![edit(8, 13, "3"), edit(0, 3, "1")],
+        )
+        .unwrap();
+
+        assert_eq!(out, b"1 two 3");
+    }
+
+    #[test]
+    fn identical_edits_are_applied_once() {
+        let out = apply_edits(None, b"one two", vec![edit(0, 3, "1"), edit(0, 3, "1")]).unwrap();
+
+        assert_eq!(out, b"1 two");
+    }
+
+    #[test]
+    fn overlapping_edits_are_reported() {
+        let err =
+            apply_edits(None, b"one two", vec![edit(0, 7, "x"), edit(4, 7, "2")]).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "overlapping edits in NO FILE at 1:1 and 1:5"
+        );
+    }
+
+    #[test]
+    fn insertions_at_the_same_place_are_reported() {
+        let err =
+            apply_edits(None, b"one two", vec![edit(3, 3, "!"), edit(3, 3, "?")]).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "overlapping edits in NO FILE at 1:4 and 1:4"
+        );
+    }
+}
@@ -0,0 +1,889 @@
+use crate::encoding::{Decoded, Encoding};
+use crate::extractor::{ExtractedFile, Extractor};
+use crate::extractor_chooser::ExtractorChooser;
+use crate::file_error::{ErrorKind, FileError};
+use crate::file_types::FileTypes;
+use crate::injection;
+use crate::input::Input;
+use crate::language::Language;
+use crate::limits::{self, Limits};
+use crate::listing::{ListFiles, ListedFile};
+use crate::notebook::{self, Notebook};
+use crate::pipeline::{Pipeline, PipelineOpts};
+use crate::query_file::QueryText;
+use crate::stats::Stats;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tree_sitter::Parser;
+
+/// Configuration for a `Searcher`. Add at least one query and one path, then
+/// call `build`.
+#[derive(Debug, Clone)]
+pub struct SearcherBuilder {
+    queries: Vec<QuerySource>,
+    paths: Vec<PathBuf>,
+    git_ignore: bool,
+    threads: Option<usize>,
+    sort: bool,
+    before_context: usize,
+    after_context: usize,
+    stdin: Option<Language>,
+    stdin_label: Option<PathBuf>,
+    file_types: FileTypes,
+    injections: bool,
+    detect_languages: bool,
+    include_generated: bool,
+    include_minified: bool,
+    max_filesize: Option<u64>,
+    modified_after: Option<SystemTime>,
+    explain_skips: bool,
+    encoding: Encoding,
+    fail_fast: bool,
+    parse_timeout: Option<Duration>,
+    query_timeout: Option<Duration>,
+    deadline: Option<Duration>,
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder {
+            queries: Vec::new(),
+            paths: Vec::new(),
+            git_ignore: true,
+            threads: None,
+            sort: false,
+            before_context: 0,
+            after_context: 0,
+            stdin: None,
+            stdin_label: None,
+            file_types: FileTypes::new(),
+            injections: false,
+            detect_languages: false,
+            include_generated: false,
+            include_minified: false,
+            max_filesize: None,
+            modified_after: None,
+            explain_skips: false,
+            encoding: Encoding::Auto,
+            fail_fast: false,
+            parse_timeout: None,
+            query_timeout: None,
+            deadline: None,
+        }
+    }
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    /// Add a query for a language. Queries without any captures get a
+    /// `@query` capture around the whole pattern.
+    pub fn query(&mut self, language: Language, query: impl Into<String>) -> &mut SearcherBuilder {
+        self.queries
+            .push(QuerySource::Inline(language, query.into()));
+        self
+    }
+
+    /// Add a query for a language from a file. The file is read when you
+    /// call `build`.
+    pub fn query_file(
+        &mut self,
+        language: Language,
+        path: impl Into<PathBuf>,
+    ) -> &mut SearcherBuilder {
+        self.queries.push(QuerySource::File(language, path.into()));
+        self
+    }
+
+    /// Add queries for several languages from one file, in sections that each
+    /// start with a `; language: NAME` line. The file is read when you call
+    /// `build`.
+    pub fn queries_file(&mut self, path: impl Into<PathBuf>) -> &mut SearcherBuilder {
+        self.queries.push(QuerySource::Sections(path.into()));
+        self
+    }
+
+    /// Add a file or directory to search.
+    pub fn path(&mut self, path: impl Into<PathBuf>) -> &mut SearcherBuilder {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Whether to respect git's ignore and exclude files (on by default.)
+    pub fn git_ignore(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.git_ignore = yes;
+        self
+    }
+
+    /// How many threads to parse files with (defaults to the number of CPUs.)
+    pub fn threads(&mut self, threads: usize) -> &mut SearcherBuilder {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Emit results in path order (off by default.)
+    pub fn sort(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.sort = yes;
+        self
+    }
+
+    /// Include lines of source before and after each match.
+    pub fn context(&mut self, before: usize, after: usize) -> &mut SearcherBuilder {
+        self.before_context = before;
+        self.after_context = after;
+        self
+    }
+
+    /// Search stdin as `language` instead of walking paths.
+    pub fn stdin(&mut self, language: Language) -> &mut SearcherBuilder {
+        self.stdin = Some(language);
+        self
+    }
+
+    /// The file name to use for stdin in results (there isn't one by
+    /// default.)
+    pub fn stdin_label(&mut self, label: impl Into<PathBuf>) -> &mut SearcherBuilder {
+        self.stdin_label = Some(label.into());
+        self
+    }
+
+    /// Change which files are parsed as which language.
+    pub fn file_types(&mut self, file_types: FileTypes) -> &mut SearcherBuilder {
+        self.file_types = file_types;
+        self
+    }
+
+    /// Also search code embedded in other files, like code blocks in
+    /// Markdown (off by default.) See the `injection` module for what counts.
+    pub fn injections(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.injections = yes;
+        self
+    }
+
+    /// Also pick languages for files by what's in them (off by default): a
+    /// `linguist-language` attribute in `.gitattributes`, or a shebang or
+    /// editor modeline in files that no glob matches.
+    pub fn detect_languages(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.detect_languages = yes;
+        self
+    }
+
+    /// Search generated and vendored code too (off by default.) See the
+    /// `generated` module for how we tell.
+    pub fn include_generated(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.include_generated = yes;
+        self
+    }
+
+    /// Search files that look minified too (off by default.) See
+    /// `skip::is_minified` for how we tell. Files that look binary are always
+    /// skipped, unless you're reading files as UTF-16.
+    pub fn include_minified(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.include_minified = yes;
+        self
+    }
+
+    /// Skip files bigger than this many bytes (there's no limit by default.)
+    pub fn max_filesize(&mut self, max: u64) -> &mut SearcherBuilder {
+        self.max_filesize = Some(max);
+        self
+    }
+
+    /// Only search files modified after `time`. If you call this more than
+    /// once, the latest time wins.
+    pub fn modified_after(&mut self, time: SystemTime) -> &mut SearcherBuilder {
+        self.modified_after = Some(self.modified_after.map_or(time, |other| other.max(time)));
+        self
+    }
+
+    /// Report each file we skip (for being generated, binary, and so on) to
+    /// the `report` callback, so you can tell people why (off by default.)
+    pub fn explain_skips(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.explain_skips = yes;
+        self
+    }
+
+    /// How to read the text in files (`Encoding::Auto` by default, which is
+    /// UTF-8 unless a file starts with a byte order mark.) Positions in
+    /// results always point into the bytes in the file, whatever the
+    /// encoding.
+    pub fn encoding(&mut self, encoding: Encoding) -> &mut SearcherBuilder {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Stop at the first file we can't search (or directory we can't walk)
+    /// and return the error, instead of reporting it and carrying on (off by
+    /// default.)
+    pub fn fail_fast(&mut self, yes: bool) -> &mut SearcherBuilder {
+        self.fail_fast = yes;
+        self
+    }
+
+    /// Skip any file that takes longer than this to parse (there's no limit
+    /// by default.) Injections and notebook cells get their own time.
+    pub fn parse_timeout(&mut self, timeout: Duration) -> &mut SearcherBuilder {
+        self.parse_timeout = Some(timeout);
+        self
+    }
+
+    /// Skip any file that takes longer than this to run the queries over
+    /// once it's parsed (there's no limit by default.)
+    pub fn query_timeout(&mut self, timeout: Duration) -> &mut SearcherBuilder {
+        self.query_timeout = Some(timeout);
+        self
+    }
+
+    /// Stop the whole search this long after the searcher is built (there's
+    /// no limit by default.) Files that are being searched at the time are
+    /// skipped, and files we haven't gotten to yet are never looked at.
+    pub fn deadline(&mut self, deadline: Duration) -> &mut SearcherBuilder {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn build(&self) -> Result<Searcher> {
+        if self.queries.is_empty() {
+            bail!("I need at least one query to search for!")
+        }
+
+        let threads = match self.threads {
+            Some(threads) => threads,
+            None => std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1),
+        };
+
+        let limits = self.limits();
+        let extractors = self.extractors(limits)?;
+
+        // this is checked again when we search, but we want bad globs to be
+        // reported before then.
+        self.file_types
+            .types_builder()?
+            .build()
+            .context("could not build a filetype matcher")?;
+
+        if let Some(language) = &self.stdin {
+            if !extractors
+                .iter()
+                .any(|extractor| extractor.language() == language)
+            {
+                bail!(
+                    "I'm supposed to search stdin as {}, but I don't have any {} queries",
+                    language,
+                    language
+                )
+            }
+        }
+
+        Ok(Searcher {
+            extractors,
+            pipeline: Pipeline::new(
+                self.paths.clone(),
+                PipelineOpts {
+                    git_ignore: self.git_ignore,
+                    threads,
+                    sort: self.sort,
+                    fail_fast: self.fail_fast,
+                    limits,
+                    explain_skips: self.explain_skips,
+                },
+            ),
+            before_context: self.before_context,
+            after_context: self.after_context,
+            stdin: self.stdin.clone(),
+            stdin_label: self.stdin_label.clone(),
+            file_types: self.file_types.clone(),
+            injections: self.injections,
+            detect_languages: self.detect_languages,
+            include_generated: self.include_generated,
+            include_minified: self.include_minified,
+            max_filesize: self.max_filesize,
+            modified_after: self.modified_after,
+            encoding: self.encoding,
+            fail_fast: self.fail_fast,
+        })
+    }
+
+    fn limits(&self) -> Limits {
+        let mut limits = Limits::new();
+        if let Some(timeout) = self.parse_timeout {
+            limits = limits.parse_timeout(timeout);
+        }
+        if let Some(timeout) = self.query_timeout {
+            limits = limits.query_timeout(timeout);
+        }
+        if let Some(deadline) = self.deadline {
+            limits = limits.deadline(Instant::now() + deadline);
+        }
+
+        limits
+    }
+
+    fn extractors(&self, limits: Limits) -> Result<Vec<Extractor>> {
+        let mut queries = Vec::with_capacity(self.queries.len());
+        for source in &self.queries {
+            match source {
+                QuerySource::Inline(lang, raw_query) => {
+                    queries.push(QueryText::inline(lang.clone(), raw_query.clone()))
+                }
+                QuerySource::File(lang, path) => queries.push(QueryText::read(lang.clone(), path)?),
+                QuerySource::Sections(path) => queries.extend(QueryText::read_sections(path)?),
+            }
+        }
+
+        // the most common case is going to be one query, so let's allocate
+        // that immediately...
+        let mut query_strings: HashMap<&Language, String> = HashMap::with_capacity(1);
+
+        // If you have two tree-sitter queries `(one)` and `(two)`, you can
+        // join them together in a single string like `(one)(two)`. In that
+        // case, the resulting query will act like an OR and match any of the
+        // queries inside. Doing this automatically gives us an advantage:
+        // for however many queries we get on the command line, we will only
+        // ever have to run one per file, since we can combine them and you
+        // can't specify queries across multiple languages! Nobody should ever
+        // notice, except that they won't see as much of a slowdown for adding
+        // new queries to an invocation as they might expect. (Well, hopefully!)
+        for query in &queries {
+            let lang = query.language();
+            let mut query_out = String::from(query.text());
+
+            let temp_query = query.parse()?;
+
+            if temp_query.pattern_count() == 0 {
+                bail!("a {} query doesn't have any patterns in it", lang)
+            }
+
+            if temp_query.capture_names().is_empty() {
+                query_out.push_str("@query");
+            }
+
+            if let Some(existing) = query_strings.get_mut(lang) {
+                existing.push_str(&query_out);
+            } else {
+                query_strings.insert(lang, query_out);
+            }
+        }
+
+        let mut out = Vec::with_capacity(query_strings.len());
+        for (lang, raw_query) in query_strings {
+            let query = lang
+                .parse_query(&raw_query)
+                .context("could not parse combined query")?;
+
+            out.push(
+                Extractor::new(lang.clone(), query)
+                    .with_context(self.before_context, self.after_context)
+                    .with_limits(limits),
+            )
+        }
+
+        // Markdown can have code in any language in it, so we need to look at
+        // Markdown files even if nobody asked about Markdown itself.
+        #[cfg(feature = "markdown")]
+        if self.injections
+            && !out
+                .iter()
+                .any(|extractor| extractor.language() == &Language::Markdown)
+        {
+            out.push(Extractor::empty(Language::Markdown)?.with_limits(limits));
+        }
+
+        Ok(out)
+    }
+}
+
+/// Where a query comes from. Files aren't read until the searcher is built, so
+/// that adding them can't fail.
+#[derive(Debug, Clone)]
+enum QuerySource {
+    Inline(Language, String),
+    File(Language, PathBuf),
+    Sections(PathBuf),
+}
+
+/// Searches files for matches to tree-sitter queries.
+///
+/// ```no_run
+/// use tree_grepper::{Language, Searcher};
+///
+/// let searcher = Searcher::builder()
+///     .query(Language::Rust, "(function_item name: (identifier)@name)")
+///     .path("src")
+///     .build()?;
+///
+/// let stats = searcher.search(
+///     |file| {
+///         for found in file.matches() {
+///             println!("{}: {}", found.name(), found.text());
+///         }
+///         Ok(())
+///     },
+///     |error| {
+///         eprintln!("{:#}", error);
+///         Ok(())
+///     },
+/// )?;
+///
+/// println!("searched {} files", stats.files_searched());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Searcher {
+    extractors: Vec<Extractor>,
+    pipeline: Pipeline,
+    before_context: usize,
+    after_context: usize,
+    stdin: Option<Language>,
+    stdin_label: Option<PathBuf>,
+    file_types: FileTypes,
+    injections: bool,
+    detect_languages: bool,
+    include_generated: bool,
+    include_minified: bool,
+    max_filesize: Option<u64>,
+    modified_after: Option<SystemTime>,
+    encoding: Encoding,
+    fail_fast: bool,
+}
+
+impl Searcher {
+    pub fn builder() -> SearcherBuilder {
+        SearcherBuilder::new()
+    }
+
+    pub fn extractors(&self) -> &[Extractor] {
+        &self.extractors
+    }
+
+    pub fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+
+    /// How files are read. Use this to decode files the same way in `run`.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Search every file, calling `emit` with each one that has matches as
+    /// soon as it's ready. Files are searched in parallel, but `emit` is
+    /// always called on the current thread. Files we can't search (and
+    /// directories we can't walk) go to `report`, on the same thread, and the
+    /// search carries on (unless we're failing fast.) If `emit` or `report`
+    /// returns an error, the search stops and the error is returned.
+    /// Otherwise, you get stats about what was searched.
+    ///
+    /// Files that take too long to search go to `report` too, with a
+    /// `TimedOut` somewhere in the error's chain (see `limits::timed_out`.)
+    /// Those are counted as skipped, not as errors. So are the files we skip
+    /// for other reasons, which go to `report` as `skip::Skipped` errors if
+    /// we're explaining skips.
+    pub fn search<'searcher, E, R>(&'searcher self, mut emit: E, report: R) -> Result<Stats>
+    where
+        E: FnMut(ExtractedFile<'searcher>) -> Result<()>,
+        R: FnMut(anyhow::Error) -> Result<()>,
+    {
+        self.run(
+            |parser, input, extractors| {
+                let source = self.encoding.decode(input.read()?);
+
+                let mut files = Vec::new();
+                for extractor in extractors {
+                    files.extend(
+                        self.extract(parser, input, &source, extractor)
+                            .with_context(|| FileError::new(ErrorKind::Parse, input.path()))?,
+                    );
+                }
+
+                Ok(if files.is_empty() { None } else { Some(files) })
+            },
+            |files| files.into_iter().try_for_each(&mut emit),
+            report,
+        )
+    }
+
+    /// Search every file, but only emit the paths of the ones that do (or
+    /// don't) have matches. This is faster than `search` since we can stop
+    /// looking at a file as soon as we find the first match. Errors go to
+    /// `report`, like in `search`.
+    pub fn list_files<E, R>(&self, which: ListFiles, emit: E, report: R) -> Result<Stats>
+    where
+        E: FnMut(ListedFile) -> Result<()>,
+        R: FnMut(anyhow::Error) -> Result<()>,
+    {
+        self.run(
+            |parser, input, extractors| {
+                let source = self.encoding.decode(input.read()?);
+
+                let mut matched = None;
+                for extractor in extractors {
+                    if self
+                        .has_match(parser, input, &source, extractor)
+                        .with_context(|| FileError::new(ErrorKind::Parse, input.path()))?
+                    {
+                        matched = Some(*extractor);
+                        break;
+                    }
+                }
+
+                // files we only looked at for injections (Markdown, when
+                // nobody asked about Markdown) don't have anything to match
+                // on their own, so they're only listed if an injection did.
+                if matched.is_none()
+                    && extractors
+                        .iter()
+                        .all(|extractor| extractor.injections_only())
+                {
+                    return Ok(None);
+                }
+
+                if matched.is_some() != (which == ListFiles::WithMatches) {
+                    return Ok(None);
+                }
+
+                // files searched as several languages are listed as the first
+                // one that matched, or the first one we tried.
+                let extractor = match matched.or_else(|| extractors.first().copied()) {
+                    Some(extractor) => extractor,
+                    None => return Ok(None),
+                };
+
+                Ok(Some(ListedFile::new(
+                    input.path().map(|path| path.to_owned()),
+                    extractor.language().to_string(),
+                )))
+            },
+            emit,
+            report,
+        )
+    }
+
+    /// Extract matches from one file, and from any injections in it if we're
+    /// looking at those. Matches from injections in the file's own language
+    /// are merged in with the rest; other languages get their own
+    /// `ExtractedFile`. Notebooks get each of their code cells searched
+    /// separately.
+    fn extract<'searcher>(
+        &'searcher self,
+        parser: &mut Parser,
+        input: &Input,
+        source: &Decoded,
+        extractor: &'searcher Extractor,
+    ) -> Result<Vec<ExtractedFile<'searcher>>> {
+        if !input.path().is_some_and(notebook::is_notebook) {
+            let mut files = self.extract_source(parser, input.path(), source.text(), extractor)?;
+            for file in &mut files {
+                file.locate_in(source);
+            }
+            return Ok(files);
+        }
+
+        let notebook = Notebook::parse(source.text())?;
+        let extractor = match self.notebook_extractor(&notebook, extractor) {
+            Some(extractor) => extractor,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut files = Vec::new();
+        for (index, cell_source) in notebook_cells(&notebook, extractor) {
+            for mut found in
+                self.extract_source(parser, input.path(), cell_source.as_bytes(), extractor)?
+            {
+                found.set_cell(index);
+                add_file(&mut files, found);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn extract_source<'searcher>(
+        &'searcher self,
+        parser: &mut Parser,
+        path: Option<&Path>,
+        source: &[u8],
+        extractor: &'searcher Extractor,
+    ) -> Result<Vec<ExtractedFile<'searcher>>> {
+        let tree = extractor.parse(source, parser)?;
+        let mut files: Vec<ExtractedFile<'searcher>> = extractor
+            .extract_from_tree(path, source, &tree)?
+            .into_iter()
+            .collect();
+
+        if !self.injections {
+            return Ok(files);
+        }
+
+        for injection in injection::find(extractor.language(), &tree, source) {
+            let guest = match self.extractor_for(injection.language()) {
+                Some(guest) => guest,
+                None => continue,
+            };
+
+            let guest_tree = guest.parse_ranges(source, injection.ranges(), parser)?;
+            if let Some(found) = guest.extract_from_tree(path, source, &guest_tree)? {
+                add_file(&mut files, found);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn has_match(
+        &self,
+        parser: &mut Parser,
+        input: &Input,
+        source: &Decoded,
+        extractor: &Extractor,
+    ) -> Result<bool> {
+        if !input.path().is_some_and(notebook::is_notebook) {
+            return self.has_match_in_source(parser, source.text(), extractor);
+        }
+
+        let notebook = Notebook::parse(source.text())?;
+        let extractor = match self.notebook_extractor(&notebook, extractor) {
+            Some(extractor) => extractor,
+            None => return Ok(false),
+        };
+
+        for (_, cell_source) in notebook_cells(&notebook, extractor) {
+            if self.has_match_in_source(parser, cell_source.as_bytes(), extractor)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn has_match_in_source(
+        &self,
+        parser: &mut Parser,
+        source: &[u8],
+        extractor: &Extractor,
+    ) -> Result<bool> {
+        let tree = extractor.parse(source, parser)?;
+        if extractor.has_match_in_tree(source, &tree)? {
+            return Ok(true);
+        }
+
+        if self.injections {
+            for injection in injection::find(extractor.language(), &tree, source) {
+                if let Some(guest) = self.extractor_for(injection.language()) {
+                    let guest_tree = guest.parse_ranges(source, injection.ranges(), parser)?;
+                    if guest.has_match_in_tree(source, &guest_tree)? {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Notebooks are picked for searching as Python files, but their kernel
+    /// can be in another language. If it says what that is, we use the
+    /// extractor for it (or skip the notebook if we don't have one.)
+    fn notebook_extractor<'searcher>(
+        &'searcher self,
+        notebook: &Notebook,
+        extractor: &'searcher Extractor,
+    ) -> Option<&'searcher Extractor> {
+        match notebook.language() {
+            None => Some(extractor),
+            Some(name) => self
+                .extractors
+                .iter()
+                .find(|extractor| extractor.language().to_string() == name),
+        }
+    }
+
+    fn extractor_for(&self, language: &Language) -> Option<&Extractor> {
+        self.extractors
+            .iter()
+            .find(|extractor| extractor.language() == language)
+    }
+
+    /// Like `search`, but with your own `work` for each file instead of
+    /// extracting matches. This is how rewrites are done, for example.
+    /// `work` gets every extractor that applies to the file (usually just
+    /// one.) Injections aren't looked at here, since `work` decides what to
+    /// do with each file.
+    pub fn run<'searcher, T, W, E, R>(
+        &'searcher self,
+        work: W,
+        mut emit: E,
+        mut report: R,
+    ) -> Result<Stats>
+    where
+        T: Send,
+        W: Fn(&mut Parser, &Input, &[&'searcher Extractor]) -> Result<Option<T>> + Sync,
+        E: FnMut(T) -> Result<()>,
+        R: FnMut(anyhow::Error) -> Result<()>,
+    {
+        // there's only one thing to read from stdin, so there's nothing to
+        // walk and no point in starting up any other threads.
+        if let Some(language) = &self.stdin {
+            let extractor = self
+                .extractor_for(language)
+                .context("couldn't find the extractor for stdin. This is an internal error and should be reported.")?;
+
+            let input = Input::Stdin(self.stdin_label.clone());
+            let mut errors = 0;
+            let mut timeouts = 0;
+            match work(&mut Parser::new(), &input, &[extractor]) {
+                Ok(Some(item)) => emit(item)?,
+                Ok(None) => (),
+                Err(error) if limits::timed_out(&error).is_some() => {
+                    timeouts += 1;
+                    report(error)?
+                }
+                Err(error) if self.fail_fast => return Err(error),
+                Err(error) => {
+                    errors += 1;
+                    report(error)?
+                }
+            }
+
+            return Ok(Stats {
+                files_searched: 1,
+                files_skipped_timeout: timeouts,
+                errors,
+                ..Stats::default()
+            });
+        }
+
+        let chooser = ExtractorChooser::from_extractors(&self.extractors, &self.file_types)
+            .and_then(|chooser| chooser.with_detection(&self.file_types, self.detect_languages))
+            .map(|chooser| {
+                chooser
+                    .skip_generated(!self.include_generated)
+                    // UTF-16 text is full of NUL bytes
+                    .skip_binary(!matches!(
+                        self.encoding,
+                        Encoding::Utf16Le | Encoding::Utf16Be
+                    ))
+                    .skip_minified(!self.include_minified)
+                    .max_filesize(self.max_filesize)
+                    .modified_after(self.modified_after)
+            })
+            .context("couldn't construct a filetype matcher")?;
+
+        self.pipeline.run(&chooser, work, emit, report)
+    }
+}
+
+/// The source of each code cell in a notebook, with its index. Python cells
+/// get their magics hidden, and cells that turn out not to be Python are
+/// skipped.
+#[cfg_attr(not(feature = "python"), allow(unused_variables))]
+fn notebook_cells(notebook: &Notebook, extractor: &Extractor) -> Vec<(usize, String)> {
+    #[cfg(feature = "python")]
+    let is_python = extractor.language() == &Language::Python;
+    #[cfg(not(feature = "python"))]
+    let is_python = false;
+
+    notebook
+        .cells()
+        .iter()
+        .filter_map(|cell| {
+            let source = if is_python {
+                cell.python_source()?
+            } else {
+                cell.source().to_string()
+            };
+
+            Some((cell.index(), source))
+        })
+        .collect()
+}
+
+/// Add `found` to `files`, merging it with the file that has the same
+/// language if there is one.
+fn add_file<'searcher>(files: &mut Vec<ExtractedFile<'searcher>>, found: ExtractedFile<'searcher>) {
+    match files
+        .iter_mut()
+        .find(|file| file.file_type() == found.file_type())
+    {
+        Some(existing) => existing.merge(found),
+        None => files.push(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn queries_for_the_same_language_are_combined() {
+        let searcher = Searcher::builder()
+            .query(Language::JavaScript, "(identifier)")
+            .query(Language::JavaScript, "(string)@string")
+            .path(".")
+            .build()
+            .unwrap();
+
+        assert_eq!(searcher.extractors().len(), 1);
+        assert_eq!(searcher.extractors()[0].captures(), ["query", "string"]);
+    }
+
+    #[test]
+    fn searching_needs_a_query() {
+        assert_eq!(
+            Searcher::builder()
+                .path(".")
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "I need at least one query to search for!"
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "javascript", feature = "rust"))]
+    fn stdin_needs_a_query_in_its_language() {
+        assert_eq!(
+            Searcher::builder()
+                .query(Language::JavaScript, "(identifier)")
+                .stdin(Language::Rust)
+                .build()
+                .unwrap_err()
+                .to_string(),
+            "I'm supposed to search stdin as rust, but I don't have any rust queries"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "javascript")]
+    fn search_emits_typed_matches() {
+        let searcher = Searcher::builder()
+            .query(Language::JavaScript, "(string_fragment)@fragment")
+            .path("tests/cmd")
+            .build()
+            .unwrap();
+
+        let mut texts = Vec::new();
+        searcher
+            .search(
+                |file| {
+                    for found in file.matches() {
+                        texts.push((found.name().to_string(), found.text().to_string()));
+                    }
+                    Ok(())
+                },
+                Err,
+            )
+            .unwrap();
+
+        assert_eq!(
+            texts,
+            vec![(String::from("fragment"), String::from("Hello, World!"))]
+        );
+    }
+}
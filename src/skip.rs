@@ -0,0 +1,149 @@
+use anyhow::{bail, Context, Result};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+/// A line longer than this near the start of a file means it's minified.
+/// People don't write lines like that by hand, but bundlers and minifiers
+/// put whole files on one.
+pub const MINIFIED_LINE_LEN: usize = 2000;
+
+/// Why we passed over a file that we'd otherwise have searched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// It's generated or vendored code. See the `generated` module.
+    Generated,
+    /// It has NUL bytes near the start, so it's probably not text.
+    Binary,
+    /// It has a very long line near the start. See `MINIFIED_LINE_LEN`.
+    Minified,
+    /// It's bigger than the maximum file size, in bytes.
+    TooBig { size: u64, max: u64 },
+    /// It hasn't been modified since the time we were asked about.
+    Unmodified,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Generated => f.write_str("it's generated or vendored code"),
+            SkipReason::Binary => f.write_str("it looks binary (it has a NUL byte near the start)"),
+            SkipReason::Minified => write!(
+                f,
+                "it looks minified (it has a line over {} bytes near the start)",
+                MINIFIED_LINE_LEN
+            ),
+            SkipReason::TooBig { size, max } => write!(
+                f,
+                "it's {} bytes, which is over the maximum of {}",
+                size, max
+            ),
+            SkipReason::Unmodified => f.write_str("it hasn't been modified recently enough"),
+        }
+    }
+}
+
+/// A file we passed over, and why. When the searcher is explaining skips,
+/// these go to `report` like errors do, but they aren't counted as errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    path: PathBuf,
+    reason: SkipReason,
+}
+
+impl Skipped {
+    pub fn new(path: impl Into<PathBuf>, reason: SkipReason) -> Skipped {
+        Skipped {
+            path: path.into(),
+            reason,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn reason(&self) -> SkipReason {
+        self.reason
+    }
+}
+
+impl Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "skipped {}: {}", self.path.display(), self.reason)
+    }
+}
+
+impl std::error::Error for Skipped {}
+
+/// Whether the start of a file looks like it isn't text. Like git, we take a
+/// NUL byte to mean binary. UTF-16 text is full of those, so a UTF-16 byte
+/// order mark means it's text no matter what.
+pub fn is_binary(head: &[u8]) -> bool {
+    if head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF]) {
+        return false;
+    }
+
+    head.contains(&0)
+}
+
+/// Whether the start of a file has a line longer than `MINIFIED_LINE_LEN`
+/// bytes. The last line in `head` might be cut off, but it only counts if it's
+/// long already.
+pub fn is_minified(head: &[u8]) -> bool {
+    head.split(|byte| *byte == b'\n')
+        .any(|line| line.len() > MINIFIED_LINE_LEN)
+}
+
+/// Parse a file size like `500`, `50K`, `2M`, or `1G`. Units are powers of
+/// 1024, and a number without one is bytes.
+pub fn parse_size(raw: &str) -> Result<u64> {
+    let raw = raw.trim();
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("expected a size like `50K`, but got `{}`", raw))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        other => bail!("unknown unit `{}` in `{}`. Try K, M, or G.", other, raw),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("{} is too big", raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nul_bytes_mean_binary() {
+        assert!(is_binary(b"\x7FELF\x02\x01\x01\x00"));
+        assert!(!is_binary(b"def main():\n    pass\n"));
+        // "hi" in UTF-16LE, with a byte order mark
+        assert!(!is_binary(b"\xFF\xFEh\x00i\x00"));
+    }
+
+    #[test]
+    fn long_lines_mean_minified() {
+        let mut head = b"// a license header\n".to_vec();
+        head.extend(std::iter::repeat_n(b'x', MINIFIED_LINE_LEN + 1));
+
+        assert!(is_minified(&head));
+        assert!(!is_minified(b"function f() {\n  return 1;\n}\n"));
+    }
+
+    #[test]
+    fn sizes_have_units() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("50K").unwrap(), 50 * 1024);
+        assert_eq!(parse_size("2mb").unwrap(), 2 * 1024 * 1024);
+        assert!(parse_size("big").is_err());
+        assert!(parse_size("3T").is_err());
+    }
+}
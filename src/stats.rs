@@ -0,0 +1,98 @@
+use std::fmt::{self, Display};
+
+/// What a search looked at, and what it passed over on purpose.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub(crate) files_searched: usize,
+    pub(crate) files_skipped_generated: usize,
+    pub(crate) files_skipped_binary: usize,
+    pub(crate) files_skipped_minified: usize,
+    pub(crate) files_skipped_too_big: usize,
+    pub(crate) files_skipped_unmodified: usize,
+    pub(crate) files_skipped_timeout: usize,
+    pub(crate) errors: usize,
+    pub(crate) deadline_passed: bool,
+}
+
+impl Stats {
+    /// How many files we parsed (or started to, if the search stopped
+    /// early.) Stdin counts as one file. Files we only looked at for
+    /// injections (Markdown, when nobody asked about Markdown) don't count.
+    pub fn files_searched(&self) -> usize {
+        self.files_searched
+    }
+
+    /// How many files we would have searched, but skipped because they're
+    /// generated or vendored code.
+    pub fn files_skipped_generated(&self) -> usize {
+        self.files_skipped_generated
+    }
+
+    /// How many files we skipped because they look binary.
+    pub fn files_skipped_binary(&self) -> usize {
+        self.files_skipped_binary
+    }
+
+    /// How many files we skipped because they look minified.
+    pub fn files_skipped_minified(&self) -> usize {
+        self.files_skipped_minified
+    }
+
+    /// How many files we skipped because they're over the maximum size.
+    pub fn files_skipped_too_big(&self) -> usize {
+        self.files_skipped_too_big
+    }
+
+    /// How many files we skipped because they weren't modified recently
+    /// enough.
+    pub fn files_skipped_unmodified(&self) -> usize {
+        self.files_skipped_unmodified
+    }
+
+    /// How many files we gave up on because parsing or querying them took
+    /// longer than we allowed. These aren't counted as errors.
+    pub fn files_skipped_timeout(&self) -> usize {
+        self.files_skipped_timeout
+    }
+
+    /// How many problems (like files we couldn't read or directories we
+    /// couldn't walk) were reported while the search kept going.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Whether the search stopped early because its deadline passed. If it
+    /// did, some files were never looked at.
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline_passed
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} files searched", self.files_searched)?;
+        writeln!(
+            f,
+            "{} generated or vendored files skipped",
+            self.files_skipped_generated
+        )?;
+        writeln!(f, "{} binary files skipped", self.files_skipped_binary)?;
+        writeln!(f, "{} minified files skipped", self.files_skipped_minified)?;
+        writeln!(
+            f,
+            "{} files skipped for being too big",
+            self.files_skipped_too_big
+        )?;
+        writeln!(
+            f,
+            "{} files skipped for not being modified recently",
+            self.files_skipped_unmodified
+        )?;
+        writeln!(
+            f,
+            "{} files skipped for taking too long",
+            self.files_skipped_timeout
+        )?;
+        writeln!(f, "{} errors", self.errors)
+    }
+}
@@ -1,9 +1,12 @@
-use anyhow::{Context, Result};
+use crate::encoding::Decoded;
+use anyhow::Result;
 use std::collections::HashSet;
 use std::io::Write;
 use tree_sitter::Tree;
 
-pub fn tree_view(tree: &Tree, source: &[u8], mut out: impl Write) -> Result<()> {
+/// Print every node in `tree` (parsed from `source`'s text), with positions in
+/// the bytes `source` was decoded from.
+pub fn tree_view(tree: &Tree, source: &Decoded, mut out: impl Write) -> Result<()> {
     let mut cursor = tree.walk();
     let mut visited: HashSet<usize> = HashSet::new();
     let mut indent = 0;
@@ -17,20 +20,20 @@ pub fn tree_view(tree: &Tree, source: &[u8], mut out: impl Write) -> Result<()>
         visited.insert(node.id());
 
         if !node_visited {
+            let start = source.original_point(node.start_byte(), node.start_position());
             write!(
                 out,
                 "{}{} {}:{}",
                 indent_str.repeat(indent),
                 node.kind(),
-                node.start_position().row + 1,
-                node.start_position().column + 1,
+                start.row + 1,
+                start.column + 1,
             )?;
             if is_leaf {
                 writeln!(
                     out,
                     ": {}",
-                    node.utf8_text(source)
-                        .context("could not get node source")?
+                    String::from_utf8_lossy(&source.text()[node.byte_range()])
                 )?;
             } else {
                 writeln!(out)?;
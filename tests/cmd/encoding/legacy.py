@@ -0,0 +1,2 @@
+# quoted
+cafe = "caf"; hello = "hllo"
@@ -0,0 +1,3 @@
+00000000: 2320 9371 756f 7465 6494 0a63 6166 6520  # .quoted..cafe 
+00000010: 3d20 2263 6166 e922 3b20 6865 6c6c 6f20  = "caf."; hello 
+00000020: 3d20 2268 e96c 6c6f 220a                 = "h.llo".
@@ -0,0 +1,5 @@
+# -*- coding: utf-8 -*-
+# Generated by the protocol buffer compiler.  DO NOT EDIT!
+
+def message():
+    pass
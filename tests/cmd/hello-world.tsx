@@ -0,0 +1 @@
+const greeting = <p>Hello, World!</p>;
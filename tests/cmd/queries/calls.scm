@@ -0,0 +1,3 @@
+; every call, with the function being called
+(call_expression
+  function: (_)@fn)@call
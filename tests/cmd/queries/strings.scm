@@ -0,0 +1,7 @@
+;; Queries for every language we care about in this project.
+
+; language: javascript
+(string_fragment)@string
+
+; language: python
+(string)@string
@@ -0,0 +1,5 @@
+; language: javascript
+(string_fragment)@string
+
+; language: javascript
+(call_expresion)@call